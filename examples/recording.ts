@@ -10,7 +10,7 @@ export async function testInputStream(device: AudioDevice) {
   const config = device.defaultInputConfig();
   console.log("Default Input Config:", config);
   
-  const buffer = new AudioBuffer();
+  const buffer = new AudioBuffer(config.channels);
   const stream = device.createInputStream(config, buffer);
   
   console.log(`Recording for ${AUDIO_CONFIG.BUFFER_DURATION_MS}ms...`);
@@ -18,7 +18,7 @@ export async function testInputStream(device: AudioDevice) {
   await delay(AUDIO_CONFIG.BUFFER_DURATION_MS);
   stream.pause();
   
-  console.log(`Recorded ${buffer.length()} samples.`);
+  console.log(`Recorded ${buffer.frames()} frames (${buffer.channels} channels).`);
   
   return { buffer, config };
 }
@@ -1,46 +1,310 @@
+use crate::callback::{WatermarkArgs, WatermarkCallback};
+use crate::error::{napi_error, spawn_coded, ErrorCode};
+use crate::ring::RingBuffer;
 use napi::bindgen_prelude::*;
+use napi::threadsafe_function::ThreadsafeFunctionCallMode;
 use napi_derive::napi;
-use std::collections::VecDeque;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+const READ_EXACT_POLL_INTERVAL: Duration = Duration::from_millis(5);
+const DEFAULT_CAPACITY_FRAMES: u32 = 48000 * 10;
+
+#[napi]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatermarkEvent {
+    /// The buffer drained to the low watermark; producers should push more audio.
+    Low,
+    /// The buffer filled to the high watermark; producers should pause (or consumers read).
+    High,
+}
+
+#[napi(object)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WatermarkOptions {
+    /// Level in frames at or below which `Low` fires.
+    pub low: Option<u32>,
+    /// Level in frames at or above which `High` fires.
+    pub high: Option<u32>,
+}
+
+/// Edge-triggered level notifications for an `AudioBuffer`.
+///
+/// `check` runs on the realtime thread, so it only touches atomics and never waits
+/// for the callback slot to be replaced.
+pub(crate) struct Watermarks {
+    low: AtomicUsize,
+    high: AtomicUsize,
+    below_low: AtomicBool,
+    above_high: AtomicBool,
+    channels: usize,
+    callback: RwLock<Option<WatermarkCallback>>,
+}
+
+impl Watermarks {
+    fn new(channels: usize) -> Self {
+        Watermarks {
+            low: AtomicUsize::new(0),
+            high: AtomicUsize::new(usize::MAX),
+            below_low: AtomicBool::new(true),
+            above_high: AtomicBool::new(false),
+            channels,
+            callback: RwLock::new(None),
+        }
+    }
+
+    pub(crate) fn check(&self, level: usize) {
+        let low = level <= self.low.load(Ordering::Relaxed);
+        let high = level >= self.high.load(Ordering::Relaxed);
+        let fire_low = low && !self.below_low.swap(true, Ordering::AcqRel);
+        let fire_high = high && !self.above_high.swap(true, Ordering::AcqRel);
+        if !low {
+            self.below_low.store(false, Ordering::Release);
+        }
+        if !high {
+            self.above_high.store(false, Ordering::Release);
+        }
+
+        if fire_low || fire_high {
+            let frames = (level / self.channels) as u32;
+            if let Ok(callback) = self.callback.try_read() {
+                if let Some(callback) = callback.as_ref() {
+                    let event = if fire_low {
+                        WatermarkEvent::Low
+                    } else {
+                        WatermarkEvent::High
+                    };
+                    callback.call(
+                        FnArgs::from((event, frames)),
+                        ThreadsafeFunctionCallMode::NonBlocking,
+                    );
+                }
+            }
+        }
+    }
+}
 
 #[napi]
+#[derive(Clone)]
 pub struct AudioBuffer {
-    pub(crate) inner: Arc<Mutex<VecDeque<f32>>>,
+    pub(crate) inner: Arc<RingBuffer>,
+    pub(crate) watermarks: Arc<Watermarks>,
+    pub(crate) channels: u16,
+    pub(crate) sample_rate: Option<u32>,
 }
 
 impl Default for AudioBuffer {
     fn default() -> Self {
-        Self::new()
+        Self::new(None, None, None)
     }
 }
 
 #[napi]
 impl AudioBuffer {
+    /// Creates a buffer of interleaved samples with `channels` per frame (mono by default)
+    /// that holds at most `capacity` frames (ten seconds at 48 kHz by default).
+    ///
+    /// Streams convert between `sampleRate` and the device's rate; without it the buffer
+    /// runs at the rate of the stream config it is used with.
     #[napi(constructor)]
-    pub fn new() -> Self {
+    pub fn new(channels: Option<u16>, capacity: Option<u32>, sample_rate: Option<u32>) -> Self {
+        let channels = channels.unwrap_or(1).max(1);
+        let capacity = capacity.unwrap_or(DEFAULT_CAPACITY_FRAMES) as usize * channels as usize;
         AudioBuffer {
-            inner: Arc::new(Mutex::new(VecDeque::with_capacity(44100))),
+            inner: Arc::new(RingBuffer::new(capacity)),
+            watermarks: Arc::new(Watermarks::new(channels as usize)),
+            channels,
+            sample_rate: sample_rate.filter(|rate| *rate > 0),
         }
     }
 
+    #[napi(getter)]
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    #[napi(getter)]
+    pub fn sample_rate(&self) -> Option<u32> {
+        self.sample_rate
+    }
+
+    /// Capacity in frames.
+    #[napi(getter)]
+    pub fn capacity(&self) -> u32 {
+        (self.inner.capacity() / self.channels as usize) as u32
+    }
+
+    /// Appends samples and returns how many fit; the rest are dropped and counted as overruns.
     #[napi]
-    pub fn push(&self, data: Float32Array) {
-        let mut buffer = self.inner.lock().unwrap();
-        for i in 0..data.len() {
-            buffer.push_back(data[i]);
-        }
+    pub fn push(&self, data: Float32Array) -> u32 {
+        let accepted = self.inner.push_slice(&data);
+        self.watermarks.check(self.inner.len());
+        accepted as u32
     }
 
     #[napi]
     pub fn clear(&self) {
-        let mut buffer = self.inner.lock().unwrap();
-        buffer.clear();
+        self.inner.clear();
+        self.watermarks.check(0);
+    }
+
+    #[napi(
+        ts_args_type = "options: WatermarkOptions, callback: (event: WatermarkEvent, frames: number) => void"
+    )]
+    pub fn set_watermarks(
+        &self,
+        options: WatermarkOptions,
+        callback: Function<'_, WatermarkArgs, ()>,
+    ) -> Result<(), ErrorCode> {
+        let low = options.low.map_or(0, |f| self.samples(f));
+        let high = options.high.map_or(usize::MAX, |f| self.samples(f));
+        if low >= high {
+            return Err(Error::new(
+                ErrorCode::InvalidArgument,
+                "Low watermark must be below the high watermark",
+            ));
+        }
+        let callback = callback
+            .build_threadsafe_function()
+            .callee_handled::<false>()
+            .weak::<true>()
+            .build()
+            .map_err(napi_error)?;
+
+        *self.watermarks.callback.write().unwrap() = Some(callback);
+        self.watermarks.low.store(low, Ordering::Relaxed);
+        self.watermarks.high.store(high, Ordering::Relaxed);
+        let level = self.inner.len();
+        self.watermarks
+            .below_low
+            .store(level <= low, Ordering::Release);
+        self.watermarks
+            .above_high
+            .store(level >= high, Ordering::Release);
+        Ok(())
+    }
+
+    #[napi]
+    pub fn clear_watermarks(&self) {
+        *self.watermarks.callback.write().unwrap() = None;
+        self.watermarks.low.store(0, Ordering::Relaxed);
+        self.watermarks.high.store(usize::MAX, Ordering::Relaxed);
+        self.watermarks.below_low.store(true, Ordering::Release);
+        self.watermarks.above_high.store(false, Ordering::Release);
     }
 
     #[napi]
     pub fn length(&self) -> u32 {
-        let buffer = self.inner.lock().unwrap();
-        buffer.len() as u32
+        self.inner.len() as u32
+    }
+
+    #[napi]
+    pub fn frames(&self) -> u32 {
+        self.length() / self.channels as u32
+    }
+
+    /// Samples dropped because the buffer was full.
+    #[napi]
+    pub fn overruns(&self) -> i64 {
+        self.inner.overruns() as i64
+    }
+
+    /// Samples an output stream had to replace with silence because the buffer was empty.
+    #[napi]
+    pub fn underruns(&self) -> i64 {
+        self.inner.underruns() as i64
+    }
+
+    /// Removes and returns up to `max_frames` of the oldest frames.
+    #[napi]
+    pub fn read(&self, max_frames: Option<u32>) -> Float32Array {
+        Float32Array::new(self.take(max_frames.map(|f| self.samples(f))))
+    }
+
+    /// Returns up to `max_frames` of the oldest frames without removing them.
+    #[napi]
+    pub fn peek(&self, max_frames: Option<u32>) -> Float32Array {
+        let count = max_frames.map_or(self.inner.len(), |f| self.samples(f));
+        let mut out = vec![0.0; count.min(self.inner.len())];
+        let read = self.inner.peek_slice(&mut out);
+        out.truncate(read);
+        Float32Array::new(out)
+    }
+
+    #[napi]
+    pub fn drain_all(&self) -> Float32Array {
+        Float32Array::new(self.take(None))
+    }
+
+    /// Fills `data` with whole frames from the front of the buffer and returns how many
+    /// samples were written.
+    #[napi]
+    pub fn read_into(&self, mut data: Float32Array) -> u32 {
+        // SAFETY: called synchronously on the JS thread, so nothing else touches the array.
+        let out = unsafe { data.as_mut() };
+        let channels = self.channels as usize;
+        let count = out.len().min(self.inner.len()) / channels * channels;
+        let read = self.inner.pop_slice(&mut out[..count]);
+        self.watermarks.check(self.inner.len());
+        read as u32
+    }
+
+    /// Resolves with exactly `frames` frames once that many have been captured, or
+    /// rejects with `Timeout` if they have not arrived within `timeout_ms`.
+    #[napi(ts_return_type = "Promise<Float32Array>")]
+    pub fn read_exact<'env>(
+        &self,
+        env: &'env Env,
+        frames: u32,
+        timeout_ms: Option<u32>,
+    ) -> Result<PromiseRaw<'env, Float32Array>> {
+        let buffer = self.clone();
+        spawn_coded(env, async move {
+            let samples = buffer.samples(frames);
+            if samples > buffer.inner.capacity() {
+                return Err(Error::new(
+                    ErrorCode::InvalidArgument,
+                    format!(
+                        "Cannot read {} frames from a buffer holding at most {}",
+                        frames,
+                        buffer.capacity()
+                    ),
+                ));
+            }
+            let wait = async {
+                while buffer.inner.len() < samples {
+                    tokio::time::sleep(READ_EXACT_POLL_INTERVAL).await;
+                }
+                Float32Array::new(buffer.take(Some(samples)))
+            };
+            match timeout_ms {
+                Some(ms) => tokio::time::timeout(Duration::from_millis(ms.into()), wait)
+                    .await
+                    .map_err(|_| {
+                        Error::new(
+                            ErrorCode::Timeout,
+                            format!("Timed out after {} ms waiting for {} frames", ms, frames),
+                        )
+                    }),
+                None => Ok(wait.await),
+            }
+        })
+    }
+}
+
+impl AudioBuffer {
+    fn samples(&self, frames: u32) -> usize {
+        frames as usize * self.channels as usize
+    }
+
+    pub(crate) fn take(&self, max_samples: Option<usize>) -> Vec<f32> {
+        let len = self.inner.len();
+        let mut out = vec![0.0; max_samples.map_or(len, |n| n.min(len))];
+        let read = self.inner.pop_slice(&mut out);
+        out.truncate(read);
+        self.watermarks.check(self.inner.len());
+        out
     }
 }
 
@@ -50,12 +314,62 @@ mod tests {
 
     #[test]
     fn test_audio_buffer() {
-        let buffer = AudioBuffer::new();
+        let buffer = AudioBuffer::new(None, None, None);
         assert_eq!(buffer.length(), 0);
+        assert_eq!(buffer.channels(), 1);
 
         // We can't easily create Float32Array in Rust tests without napi context
         // but we can test the inner logic if we want, or just verify it doesn't crash
         buffer.clear();
         assert_eq!(buffer.length(), 0);
     }
+
+    #[test]
+    fn test_take_is_fifo() {
+        let buffer = AudioBuffer::new(None, None, None);
+        buffer.inner.push_slice(&[0.1, 0.2, 0.3, 0.4]);
+
+        assert_eq!(buffer.take(Some(2)), vec![0.1, 0.2]);
+        assert_eq!(buffer.length(), 2);
+        assert_eq!(buffer.take(Some(10)), vec![0.3, 0.4]);
+        assert!(buffer.take(None).is_empty());
+    }
+
+    #[test]
+    fn test_frames_follow_channel_count() {
+        let buffer = AudioBuffer::new(Some(2), Some(8), None);
+        buffer.inner.push_slice(&[0.1, 0.2, 0.3, 0.4, 0.5]);
+
+        assert_eq!(buffer.length(), 5);
+        assert_eq!(buffer.frames(), 2);
+        assert_eq!(buffer.capacity(), 8);
+    }
+
+    #[test]
+    fn test_watermarks_are_edge_triggered() {
+        let watermarks = Watermarks::new(1);
+        watermarks.low.store(2, Ordering::Relaxed);
+        watermarks.high.store(8, Ordering::Relaxed);
+
+        watermarks.check(5);
+        assert!(!watermarks.below_low.load(Ordering::Acquire));
+        watermarks.check(9);
+        assert!(watermarks.above_high.load(Ordering::Acquire));
+        watermarks.check(1);
+        assert!(watermarks.below_low.load(Ordering::Acquire));
+        assert!(!watermarks.above_high.load(Ordering::Acquire));
+    }
+
+    #[test]
+    fn test_clear_watermarks_resets_latches() {
+        let buffer = AudioBuffer::new(None, Some(16), None);
+        buffer.watermarks.high.store(8, Ordering::Relaxed);
+        buffer.watermarks.check(9);
+        assert!(buffer.watermarks.above_high.load(Ordering::Acquire));
+        assert!(!buffer.watermarks.below_low.load(Ordering::Acquire));
+
+        buffer.clear_watermarks();
+        assert!(!buffer.watermarks.above_high.load(Ordering::Acquire));
+        assert!(buffer.watermarks.below_low.load(Ordering::Acquire));
+    }
 }
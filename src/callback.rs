@@ -0,0 +1,533 @@
+use crate::buffer::WatermarkEvent;
+use crate::duplex::{DuplexProcessor, DuplexStreamOptions};
+use crate::error::StreamErrorEvent;
+use crate::ring::RingBuffer;
+use crate::stream::{
+    InputCallbackInfo, InputStreamTimestamp, OutputCallbackInfo, OutputStreamTimestamp,
+    StreamInstant, StreamStatus,
+};
+use crate::watch::DeviceChangeEvent;
+use napi::bindgen_prelude::*;
+use napi::threadsafe_function::{ThreadsafeFunction, ThreadsafeFunctionCallMode};
+use napi_derive::napi;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+pub(crate) type RenderArgs = FnArgs<(u32, OutputCallbackInfo)>;
+pub(crate) type RenderCallback =
+    ThreadsafeFunction<RenderArgs, Float32Array, RenderArgs, Status, false, true>;
+pub(crate) type CaptureArgs = FnArgs<(Float32Array, InputCallbackInfo)>;
+pub(crate) type CaptureCallback =
+    ThreadsafeFunction<CaptureArgs, (), CaptureArgs, Status, false, true>;
+pub(crate) type DuplexArgs = FnArgs<(Float32Array, OutputCallbackInfo)>;
+pub(crate) type DuplexCallback =
+    ThreadsafeFunction<DuplexArgs, Float32Array, DuplexArgs, Status, false, true>;
+pub(crate) type StreamErrorCallback =
+    ThreadsafeFunction<StreamErrorEvent, (), StreamErrorEvent, Status, false, true>;
+pub(crate) type ChunkCallback = ThreadsafeFunction<Buffer, (), Buffer, Status, false, true>;
+pub(crate) type EndedCallback = ThreadsafeFunction<(), (), (), Status, false, true>;
+pub(crate) type DeviceChangeCallback =
+    ThreadsafeFunction<DeviceChangeEvent, (), DeviceChangeEvent, Status, false, true>;
+pub(crate) type WatermarkArgs = FnArgs<(WatermarkEvent, u32)>;
+pub(crate) type WatermarkCallback =
+    ThreadsafeFunction<WatermarkArgs, (), WatermarkArgs, Status, false, true>;
+
+const DEFAULT_BLOCK_FRAMES: u32 = 512;
+const DEFAULT_PREROLL_BLOCKS: u32 = 4;
+/// How long an offline render waits for JavaScript before padding with silence.
+pub(crate) const OFFLINE_WAIT: Duration = Duration::from_secs(5);
+/// Blocks a `BlockWorker` holds before dropping new ones.
+const HANDOFF_BLOCKS: usize = 256;
+/// Longest a `BlockWorker` sleeps when nothing wakes it.
+const HANDOFF_INTERVAL: Duration = Duration::from_millis(5);
+
+#[napi(object)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OutputCallbackOptions {
+    /// Frames requested from JavaScript per call.
+    pub block_frames: Option<u32>,
+    /// Number of blocks kept queued ahead of the device.
+    pub preroll_blocks: Option<u32>,
+}
+
+/// Feeds an output stream from a JavaScript render function.
+///
+/// The realtime callback only ever drains the queue; refills are requested
+/// through a non-blocking threadsafe call and land in the queue once JS returns.
+/// Calls that throw or cannot be queued are reported through `status`.
+pub(crate) struct CallbackRenderer {
+    queue: Arc<RingBuffer>,
+    pending: Arc<AtomicBool>,
+    callback: RenderCallback,
+    status: Arc<StreamStatus>,
+    channels: usize,
+    block_frames: u32,
+    target_samples: usize,
+    realtime: bool,
+}
+
+impl CallbackRenderer {
+    pub(crate) fn new(
+        callback: &Function<'_, RenderArgs, Float32Array>,
+        channels: u16,
+        options: Option<OutputCallbackOptions>,
+        realtime: bool,
+        status: Arc<StreamStatus>,
+    ) -> Result<Self> {
+        let options = options.unwrap_or_default();
+        let block_frames = options.block_frames.unwrap_or(DEFAULT_BLOCK_FRAMES).max(1);
+        let preroll_blocks = options
+            .preroll_blocks
+            .unwrap_or(DEFAULT_PREROLL_BLOCKS)
+            .max(1);
+        let channels = channels.max(1) as usize;
+        let target_samples = (block_frames * preroll_blocks) as usize * channels;
+
+        let queue = RingBuffer::new(target_samples * 2);
+        // Pre-roll synchronously so the first device callbacks already have audio.
+        while queue.len() < target_samples {
+            let block =
+                callback.call(FnArgs::from((block_frames, OutputCallbackInfo::default())))?;
+            if block.is_empty() || queue.push_slice(&block) < block.len() {
+                break;
+            }
+        }
+
+        let callback = callback
+            .build_threadsafe_function()
+            .callee_handled::<false>()
+            .weak::<true>()
+            .build()?;
+
+        Ok(CallbackRenderer {
+            queue: Arc::new(queue),
+            pending: Arc::new(AtomicBool::new(false)),
+            callback,
+            status,
+            channels,
+            block_frames,
+            target_samples,
+            realtime,
+        })
+    }
+
+    pub(crate) fn render(&self, data: &mut [f32], info: &cpal::OutputCallbackInfo) {
+        if !self.realtime {
+            self.wait_for(data.len(), info);
+        }
+        self.queue.pop_or_silence(data);
+        let queued = self.queue.len();
+
+        if queued < self.target_samples {
+            let deficit = ((self.target_samples - queued) / self.channels) as u32;
+            self.request(deficit.max(self.block_frames), info);
+        }
+    }
+
+    /// Offline streams have no deadline, so block until JavaScript has queued `samples`,
+    /// gives up by returning too little, or takes longer than `OFFLINE_WAIT`.
+    fn wait_for(&self, samples: usize, info: &cpal::OutputCallbackInfo) {
+        let deadline = Instant::now() + OFFLINE_WAIT;
+        let mut requested = false;
+        while self.queue.len() < samples && Instant::now() < deadline {
+            if !self.pending.load(Ordering::Acquire) {
+                if requested {
+                    return;
+                }
+                let deficit = ((samples - self.queue.len()) / self.channels) as u32;
+                self.request(deficit.max(self.block_frames), info);
+                requested = true;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn request(&self, frames: u32, info: &cpal::OutputCallbackInfo) {
+        if self.pending.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        let (queue, pending, status) = (
+            self.queue.clone(),
+            self.pending.clone(),
+            self.status.clone(),
+        );
+        let queued = self.callback.call_with_return_value(
+            FnArgs::from((frames, OutputCallbackInfo::from(info))),
+            ThreadsafeFunctionCallMode::NonBlocking,
+            move |result, _| {
+                match result {
+                    Ok(block) => {
+                        queue.push_slice(&block);
+                    }
+                    Err(err) => {
+                        status.report_error(format!("Render callback failed: {}", err.reason))
+                    }
+                }
+                pending.store(false, Ordering::Release);
+                Ok(())
+            },
+        );
+        if queued != Status::Ok {
+            self.pending.store(false, Ordering::Release);
+            self.status
+                .report_error(format!("Could not call the render callback: {}", queued));
+        }
+    }
+}
+
+/// When a block reached its audio callback, kept without the direction it came from.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct BlockTiming {
+    callback: StreamInstant,
+    device: StreamInstant,
+    latency: f64,
+}
+
+impl From<&cpal::InputCallbackInfo> for BlockTiming {
+    fn from(info: &cpal::InputCallbackInfo) -> Self {
+        let info = InputCallbackInfo::from(info);
+        BlockTiming {
+            callback: info.timestamp.callback,
+            device: info.timestamp.capture,
+            latency: info.latency,
+        }
+    }
+}
+
+impl From<&cpal::OutputCallbackInfo> for BlockTiming {
+    fn from(info: &cpal::OutputCallbackInfo) -> Self {
+        let info = OutputCallbackInfo::from(info);
+        BlockTiming {
+            callback: info.timestamp.callback,
+            device: info.timestamp.playback,
+            latency: info.latency,
+        }
+    }
+}
+
+impl From<BlockTiming> for InputCallbackInfo {
+    fn from(timing: BlockTiming) -> Self {
+        InputCallbackInfo {
+            timestamp: InputStreamTimestamp {
+                callback: timing.callback,
+                capture: timing.device,
+            },
+            latency: timing.latency,
+        }
+    }
+}
+
+impl From<BlockTiming> for OutputCallbackInfo {
+    fn from(timing: BlockTiming) -> Self {
+        OutputCallbackInfo {
+            timestamp: OutputStreamTimestamp {
+                callback: timing.callback,
+                playback: timing.device,
+            },
+            latency: timing.latency,
+        }
+    }
+}
+
+#[derive(Default)]
+struct BlockSlot {
+    len: AtomicUsize,
+    callback: AtomicI64,
+    device: AtomicI64,
+    latency: AtomicU64,
+}
+
+/// Blocks waiting for a `BlockWorker`: samples in a ring, timings in fixed slots beside it.
+///
+/// Meant for one producer and one consumer; neither side allocates except for the
+/// `Vec` the consumer pops.
+struct BlockQueue {
+    samples: RingBuffer,
+    slots: Box<[BlockSlot]>,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    stop: AtomicBool,
+}
+
+impl BlockQueue {
+    fn push(&self, data: &[f32], timing: BlockTiming) -> bool {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Acquire);
+        let free = self.samples.capacity() - self.samples.len();
+        if tail.wrapping_sub(head) >= self.slots.len() || data.len() > free {
+            return false;
+        }
+
+        self.samples.push_slice(data);
+        let slot = &self.slots[tail % self.slots.len()];
+        slot.len.store(data.len(), Ordering::Relaxed);
+        slot.callback
+            .store(timing.callback.as_nanos(), Ordering::Relaxed);
+        slot.device
+            .store(timing.device.as_nanos(), Ordering::Relaxed);
+        slot.latency
+            .store(timing.latency.to_bits(), Ordering::Relaxed);
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        true
+    }
+
+    fn pop(&self) -> Option<(Vec<f32>, BlockTiming)> {
+        let head = self.head.load(Ordering::Relaxed);
+        if head == self.tail.load(Ordering::Acquire) {
+            return None;
+        }
+
+        let slot = &self.slots[head % self.slots.len()];
+        let mut block = vec![0.0; slot.len.load(Ordering::Relaxed)];
+        self.samples.pop_slice(&mut block);
+        let timing = BlockTiming {
+            callback: StreamInstant::from_nanos(slot.callback.load(Ordering::Relaxed)),
+            device: StreamInstant::from_nanos(slot.device.load(Ordering::Relaxed)),
+            latency: f64::from_bits(slot.latency.load(Ordering::Relaxed)),
+        };
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+        Some((block, timing))
+    }
+}
+
+/// Moves blocks off an audio thread to a thread that may allocate and call JavaScript.
+///
+/// `send` only copies into preallocated storage and wakes the thread; a block that
+/// does not fit because JavaScript has fallen behind is dropped whole.
+pub(crate) struct BlockWorker {
+    queue: Arc<BlockQueue>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl BlockWorker {
+    /// Starts a thread passing every block to `deliver`, with room for `capacity` samples.
+    pub(crate) fn spawn<F>(capacity: usize, mut deliver: F) -> Self
+    where
+        F: FnMut(Vec<f32>, BlockTiming) + Send + 'static,
+    {
+        let queue = Arc::new(BlockQueue {
+            samples: RingBuffer::new(capacity),
+            slots: (0..HANDOFF_BLOCKS).map(|_| BlockSlot::default()).collect(),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            stop: AtomicBool::new(false),
+        });
+        let source = queue.clone();
+        let handle = std::thread::spawn(move || loop {
+            // Check before draining so nothing sent before the drop is missed.
+            let last = source.stop.load(Ordering::Acquire);
+            while let Some((block, timing)) = source.pop() {
+                deliver(block, timing);
+            }
+            if last {
+                return;
+            }
+            std::thread::park_timeout(HANDOFF_INTERVAL);
+        });
+        BlockWorker {
+            queue,
+            handle: Some(handle),
+        }
+    }
+
+    /// Queues a block for delivery; returns false if it was dropped.
+    pub(crate) fn send(&self, data: &[f32], timing: BlockTiming) -> bool {
+        let sent = self.queue.push(data, timing);
+        if let Some(handle) = &self.handle {
+            handle.thread().unpark();
+        }
+        sent
+    }
+}
+
+impl Drop for BlockWorker {
+    fn drop(&mut self) {
+        self.queue.stop.store(true, Ordering::Release);
+        if let Some(handle) = self.handle.take() {
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Hands each captured block to a JavaScript function as an interleaved `Float32Array`.
+///
+/// The audio callback only copies the block into a `BlockWorker`, whose thread builds
+/// the array and makes the call.
+pub(crate) struct CallbackCapturer {
+    worker: BlockWorker,
+}
+
+impl CallbackCapturer {
+    pub(crate) fn new(callback: &Function<'_, CaptureArgs, ()>, capacity: usize) -> Result<Self> {
+        let callback: CaptureCallback = callback
+            .build_threadsafe_function()
+            .callee_handled::<false>()
+            .weak::<true>()
+            .build()?;
+        let worker = BlockWorker::spawn(capacity, move |block, timing| {
+            callback.call(
+                FnArgs::from((Float32Array::new(block), InputCallbackInfo::from(timing))),
+                ThreadsafeFunctionCallMode::NonBlocking,
+            );
+        });
+        Ok(CallbackCapturer { worker })
+    }
+
+    pub(crate) fn capture(&self, data: &[f32], info: &cpal::InputCallbackInfo) {
+        self.worker.send(data, info.into());
+    }
+}
+
+/// Runs a JavaScript function on captured input and plays what it returns.
+///
+/// Input goes out in fixed blocks through a `BlockWorker` without waiting for results;
+/// output is played from a queue primed with silence, which gives JavaScript the preroll
+/// to answer each block. A result of the wrong length, or a throw, is reported and played
+/// as silence.
+pub(crate) struct CallbackProcessor {
+    worker: BlockWorker,
+    queue: Arc<RingBuffer>,
+    pending: Vec<f32>,
+    block_samples: usize,
+}
+
+impl CallbackProcessor {
+    pub(crate) fn new(
+        callback: &Function<'_, DuplexArgs, Float32Array>,
+        input_channels: u16,
+        output_channels: u16,
+        options: &DuplexStreamOptions,
+        status: Arc<StreamStatus>,
+    ) -> Result<Self> {
+        let block_frames = options.block_frames.unwrap_or(DEFAULT_BLOCK_FRAMES).max(1) as usize;
+        let preroll_blocks = options
+            .preroll_blocks
+            .unwrap_or(DEFAULT_PREROLL_BLOCKS)
+            .max(1) as usize;
+        let output_samples = block_frames * output_channels.max(1) as usize;
+        let block_samples = block_frames * input_channels.max(1) as usize;
+        let preroll = output_samples * preroll_blocks;
+
+        let queue = Arc::new(RingBuffer::new(preroll * 2));
+        queue.push_slice(&vec![0.0; preroll]);
+
+        let callback: DuplexCallback = callback
+            .build_threadsafe_function()
+            .callee_handled::<false>()
+            .weak::<true>()
+            .build()?;
+        let results = queue.clone();
+        let worker =
+            BlockWorker::spawn(block_samples * preroll_blocks * 2, move |block, timing| {
+                let (queue, status) = (results.clone(), status.clone());
+                callback.call_with_return_value(
+                    FnArgs::from((Float32Array::new(block), OutputCallbackInfo::from(timing))),
+                    ThreadsafeFunctionCallMode::NonBlocking,
+                    move |result, _| {
+                        match result {
+                            Ok(block) if block.len() == output_samples => {
+                                queue.push_slice(&block);
+                            }
+                            Ok(block) => {
+                                status.report_error(format!(
+                                    "Duplex callback returned {} samples instead of {}",
+                                    block.len(),
+                                    output_samples
+                                ));
+                                queue.push_slice(&vec![0.0; output_samples]);
+                            }
+                            Err(err) => {
+                                status.report_error(format!(
+                                    "Duplex callback failed: {}",
+                                    err.reason
+                                ));
+                                queue.push_slice(&vec![0.0; output_samples]);
+                            }
+                        }
+                        Ok(())
+                    },
+                );
+            });
+
+        Ok(CallbackProcessor {
+            worker,
+            queue,
+            pending: Vec::with_capacity(block_samples),
+            block_samples,
+        })
+    }
+}
+
+impl DuplexProcessor for CallbackProcessor {
+    fn process(&mut self, input: &[f32], output: &mut [f32], info: &cpal::OutputCallbackInfo) {
+        let mut input = input;
+        while !input.is_empty() {
+            // Never beyond the capacity reserved in `new`, so this doesn't allocate.
+            let take = (self.block_samples - self.pending.len()).min(input.len());
+            self.pending.extend_from_slice(&input[..take]);
+            input = &input[take..];
+            if self.pending.len() == self.block_samples {
+                self.worker.send(&self.pending, info.into());
+                self.pending.clear();
+            }
+        }
+        self.queue.pop_or_silence(output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    fn timing(latency: f64) -> BlockTiming {
+        BlockTiming {
+            latency,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_block_queue_drops_blocks_that_do_not_fit() {
+        let queue = BlockQueue {
+            samples: RingBuffer::new(3),
+            slots: (0..2).map(|_| BlockSlot::default()).collect(),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            stop: AtomicBool::new(false),
+        };
+        assert!(queue.push(&[0.1, 0.2], timing(0.5)));
+        assert!(!queue.push(&[0.3, 0.4], timing(0.0)));
+        assert!(queue.push(&[0.3], timing(0.25)));
+        let (block, first) = queue.pop().unwrap();
+        assert_eq!((block, first.latency), (vec![0.1, 0.2], 0.5));
+        assert!(queue.push(&[0.6], timing(0.0)));
+        // Out of slots even though the ring still has room.
+        assert!(!queue.push(&[0.7], timing(0.0)));
+
+        assert_eq!(queue.pop().unwrap().0, [0.3]);
+        assert_eq!(queue.pop().unwrap().0, [0.6]);
+        assert!(queue.pop().is_none());
+    }
+
+    #[test]
+    fn test_block_worker_delivers_everything_sent_before_drop() {
+        let delivered = Arc::new(Mutex::new(Vec::new()));
+        let sink = delivered.clone();
+        let worker = BlockWorker::spawn(64, move |block, timing: BlockTiming| {
+            sink.lock().unwrap().push((block, timing.latency));
+        });
+        assert!(worker.send(&[0.1, 0.2], timing(0.5)));
+        assert!(worker.send(&[0.3], timing(0.25)));
+        drop(worker);
+
+        assert_eq!(
+            *delivered.lock().unwrap(),
+            [(vec![0.1, 0.2], 0.5), (vec![0.3], 0.25)]
+        );
+    }
+}
@@ -0,0 +1,106 @@
+use crate::error::ErrorCode;
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+
+/// How frames are converted when the buffer and stream channel counts differ.
+#[napi]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChannelMapping {
+    /// Destination channel `i` takes source channel `i % sourceChannels`.
+    #[default]
+    Duplicate,
+    /// Every destination channel takes the mean of all source channels.
+    Average,
+    /// Every destination channel takes the given source channel.
+    Select(u16),
+}
+
+impl ChannelMapping {
+    /// Rejects a `Select` of a channel that sources with `source_channels` don't have.
+    pub(crate) fn validate(&self, source_channels: u16) -> Result<(), ErrorCode> {
+        match *self {
+            ChannelMapping::Select(channel) if channel >= source_channels => Err(Error::new(
+                ErrorCode::InvalidArgument,
+                format!(
+                    "Cannot select channel {} of {} source channels",
+                    channel, source_channels
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn map_frame(&self, src: &[f32], dst: &mut [f32]) {
+        if src.is_empty() {
+            dst.fill(0.0);
+            return;
+        }
+        if src.len() == dst.len() {
+            dst.copy_from_slice(src);
+            return;
+        }
+
+        match *self {
+            ChannelMapping::Duplicate => {
+                for (i, sample) in dst.iter_mut().enumerate() {
+                    *sample = src[i % src.len()];
+                }
+            }
+            ChannelMapping::Average => {
+                let mean = src.iter().sum::<f32>() / src.len() as f32;
+                dst.fill(mean);
+            }
+            ChannelMapping::Select(channel) => {
+                let value = src.get(channel as usize).copied().unwrap_or(0.0);
+                dst.fill(value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duplicate() {
+        let mut dst = [0.0; 2];
+        ChannelMapping::Duplicate.map_frame(&[0.5], &mut dst);
+        assert_eq!(dst, [0.5, 0.5]);
+
+        let mut dst = [0.0; 1];
+        ChannelMapping::Duplicate.map_frame(&[0.25, 0.75], &mut dst);
+        assert_eq!(dst, [0.25]);
+    }
+
+    #[test]
+    fn test_average() {
+        let mut dst = [0.0; 1];
+        ChannelMapping::Average.map_frame(&[0.25, 0.75], &mut dst);
+        assert_eq!(dst, [0.5]);
+    }
+
+    #[test]
+    fn test_select() {
+        let mut dst = [0.0; 1];
+        ChannelMapping::Select(1).map_frame(&[0.25, 0.75], &mut dst);
+        assert_eq!(dst, [0.75]);
+
+        ChannelMapping::Select(9).map_frame(&[0.25, 0.75], &mut dst);
+        assert_eq!(dst, [0.0]);
+    }
+
+    #[test]
+    fn test_validate_select() {
+        assert!(ChannelMapping::Select(1).validate(2).is_ok());
+        assert!(ChannelMapping::Select(2).validate(2).is_err());
+        assert!(ChannelMapping::Duplicate.validate(2).is_ok());
+    }
+
+    #[test]
+    fn test_matching_channels_copy() {
+        let mut dst = [0.0; 2];
+        ChannelMapping::Average.map_frame(&[0.25, 0.75], &mut dst);
+        assert_eq!(dst, [0.25, 0.75]);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::resampler::ResampleQuality;
 use crate::types::SampleFormat;
 use napi_derive::napi;
 
@@ -32,6 +33,9 @@ pub struct StreamConfig {
     pub channels: u16,
     pub sample_rate: u32,
     pub buffer_size: BufferSize,
+    pub sample_format: Option<SampleFormat>,
+    /// Converter used when the device runs at another rate than `sample_rate`.
+    pub resample_quality: Option<ResampleQuality>,
 }
 
 impl From<cpal::StreamConfig> for StreamConfig {
@@ -40,6 +44,20 @@ impl From<cpal::StreamConfig> for StreamConfig {
             channels: c.channels,
             sample_rate: c.sample_rate,
             buffer_size: c.buffer_size.into(),
+            sample_format: None,
+            resample_quality: None,
+        }
+    }
+}
+
+impl From<cpal::SupportedStreamConfig> for StreamConfig {
+    fn from(c: cpal::SupportedStreamConfig) -> Self {
+        StreamConfig {
+            channels: c.channels(),
+            sample_rate: c.sample_rate(),
+            buffer_size: BufferSize::Default,
+            sample_format: Some(c.sample_format().into()),
+            resample_quality: None,
         }
     }
 }
@@ -111,3 +129,204 @@ impl From<cpal::SupportedStreamConfigRange> for SupportedStreamConfigRange {
         }
     }
 }
+
+/// What `findOutputConfig`/`findInputConfig` should look for; omitted fields default to
+/// the device's default config.
+#[napi(object)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConfigRequest {
+    pub channels: Option<u16>,
+    pub sample_rate: Option<u32>,
+    pub sample_format: Option<SampleFormat>,
+    pub buffer_size: Option<BufferSize>,
+}
+
+/// How a requested config is matched against what a device supports.
+#[napi]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConfigStrategy {
+    /// Only a config with exactly the requested channels, rate, format and buffer size.
+    Exact,
+    /// The closest channel count, then the closest rate, then the requested format.
+    #[default]
+    Nearest,
+    /// Like `Nearest`, but a float format wins over an integer one that was asked for.
+    PreferFloat,
+}
+
+/// A fully specified request, with the device's defaults filled in.
+pub(crate) struct ConfigTarget {
+    pub(crate) channels: u16,
+    pub(crate) sample_rate: u32,
+    pub(crate) sample_format: cpal::SampleFormat,
+    pub(crate) buffer_size: BufferSize,
+}
+
+/// Picks the supported config closest to `target`, or `None` when nothing qualifies.
+pub(crate) fn find_config(
+    ranges: &[cpal::SupportedStreamConfigRange],
+    target: &ConfigTarget,
+    strategy: ConfigStrategy,
+) -> Option<StreamConfig> {
+    ranges
+        .iter()
+        .filter_map(|range| {
+            let sample_rate = target
+                .sample_rate
+                .clamp(range.min_sample_rate(), range.max_sample_rate());
+            let buffer_size = fit_buffer_size(target.buffer_size, range.buffer_size());
+            let format = range.sample_format();
+            if strategy == ConfigStrategy::Exact
+                && (range.channels() != target.channels
+                    || sample_rate != target.sample_rate
+                    || format != target.sample_format
+                    || buffer_size != target.buffer_size)
+            {
+                return None;
+            }
+            let key = (
+                range.channels().abs_diff(target.channels),
+                // Between equally distant counts, more channels beats dropping some.
+                range.channels() < target.channels,
+                strategy == ConfigStrategy::PreferFloat && !format.is_float(),
+                sample_rate.abs_diff(target.sample_rate),
+                format != target.sample_format,
+                format_rank(format),
+            );
+            let config = StreamConfig {
+                channels: range.channels(),
+                sample_rate,
+                buffer_size,
+                sample_format: Some(format.into()),
+                resample_quality: None,
+            };
+            Some((key, config))
+        })
+        .min_by_key(|(key, _)| *key)
+        .map(|(_, config)| config)
+}
+
+/// Whether some range supports `config` in `sample_format` exactly.
+pub(crate) fn supports_config(
+    ranges: &[cpal::SupportedStreamConfigRange],
+    config: &StreamConfig,
+    sample_format: cpal::SampleFormat,
+) -> bool {
+    let target = ConfigTarget {
+        channels: config.channels,
+        sample_rate: config.sample_rate,
+        sample_format,
+        buffer_size: config.buffer_size,
+    };
+    find_config(ranges, &target, ConfigStrategy::Exact).is_some()
+}
+
+/// `requested`, moved into the supported range when it falls outside it.
+fn fit_buffer_size(requested: BufferSize, supported: &cpal::SupportedBufferSize) -> BufferSize {
+    match (requested, supported) {
+        (BufferSize::Fixed(frames), cpal::SupportedBufferSize::Range { min, max }) => {
+            BufferSize::Fixed(frames.clamp(*min, (*max).max(*min)))
+        }
+        (requested, _) => requested,
+    }
+}
+
+/// Lower is better: `F32` (what streams convert to anyway), other floats, then wider integers.
+fn format_rank(format: cpal::SampleFormat) -> (bool, bool, std::cmp::Reverse<u32>) {
+    (
+        format != cpal::SampleFormat::F32,
+        !format.is_float(),
+        std::cmp::Reverse(format.bits_per_sample()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(
+        channels: u16,
+        min: u32,
+        max: u32,
+        format: cpal::SampleFormat,
+    ) -> cpal::SupportedStreamConfigRange {
+        cpal::SupportedStreamConfigRange::new(
+            channels,
+            min,
+            max,
+            cpal::SupportedBufferSize::Range { min: 64, max: 4096 },
+            format,
+        )
+    }
+
+    fn target(channels: u16, sample_rate: u32, sample_format: cpal::SampleFormat) -> ConfigTarget {
+        ConfigTarget {
+            channels,
+            sample_rate,
+            sample_format,
+            buffer_size: BufferSize::Default,
+        }
+    }
+
+    #[test]
+    fn test_nearest_prefers_channels_then_rate_then_format() {
+        let ranges = [
+            range(2, 44100, 44100, cpal::SampleFormat::I16),
+            range(2, 48000, 48000, cpal::SampleFormat::I16),
+            range(2, 48000, 48000, cpal::SampleFormat::F32),
+            range(6, 48000, 48000, cpal::SampleFormat::I16),
+        ];
+        let found = find_config(
+            &ranges,
+            &target(2, 47000, cpal::SampleFormat::I16),
+            ConfigStrategy::Nearest,
+        )
+        .unwrap();
+        assert_eq!((found.channels, found.sample_rate), (2, 48000));
+        assert_eq!(found.sample_format, Some(SampleFormat::I16));
+
+        let found = find_config(
+            &ranges,
+            &target(2, 47000, cpal::SampleFormat::I16),
+            ConfigStrategy::PreferFloat,
+        )
+        .unwrap();
+        assert_eq!(found.sample_format, Some(SampleFormat::F32));
+
+        let found = find_config(
+            &ranges,
+            &target(5, 44100, cpal::SampleFormat::I16),
+            ConfigStrategy::Nearest,
+        )
+        .unwrap();
+        assert_eq!((found.channels, found.sample_rate), (6, 48000));
+    }
+
+    #[test]
+    fn test_exact_needs_every_field() {
+        let ranges = [range(2, 8000, 96000, cpal::SampleFormat::F32)];
+        let mut wanted = target(2, 22050, cpal::SampleFormat::F32);
+        assert!(find_config(&ranges, &wanted, ConfigStrategy::Exact).is_some());
+        wanted.buffer_size = BufferSize::Fixed(8192);
+        assert!(find_config(&ranges, &wanted, ConfigStrategy::Exact).is_none());
+        let found = find_config(&ranges, &wanted, ConfigStrategy::Nearest).unwrap();
+        assert_eq!(found.buffer_size, BufferSize::Fixed(4096));
+
+        let config = StreamConfig {
+            channels: 2,
+            sample_rate: 192000,
+            buffer_size: BufferSize::Default,
+            sample_format: None,
+            resample_quality: None,
+        };
+        assert!(!supports_config(&ranges, &config, cpal::SampleFormat::F32));
+        assert!(supports_config(
+            &ranges,
+            &StreamConfig {
+                sample_rate: 48000,
+                ..config
+            },
+            cpal::SampleFormat::F32
+        ));
+    }
+}
@@ -0,0 +1,219 @@
+use crate::player::AudioFileInfo;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{Decoder, DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error as DecodeError;
+use symphonia::core::formats::{FormatOptions, FormatReader, SeekMode, SeekTo};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, StandardTagKey, Tag};
+use symphonia::core::probe::Hint;
+use symphonia::core::units::Time;
+
+/// Reads an audio file packet by packet as interleaved `f32`, whatever its sample format.
+pub(crate) struct FileDecoder {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    channels: u16,
+    sample_rate: u32,
+    info: AudioFileInfo,
+    samples: Option<SampleBuffer<f32>>,
+    /// Timestamp a seek asked for; earlier frames of the packet it landed in are dropped.
+    seek_target: u64,
+}
+
+impl FileDecoder {
+    pub(crate) fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let source = MediaSourceStream::new(Box::new(File::open(path)?), Default::default());
+        let mut hint = Hint::new();
+        if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+            hint.with_extension(extension);
+        }
+        let probed = symphonia::default::get_probe()
+            .format(
+                &hint,
+                source,
+                &FormatOptions::default(),
+                &MetadataOptions::default(),
+            )
+            .map_err(into_io)?;
+        let mut format = probed.format;
+        // Container tags (e.g. ID3 before MP3 frames) first, then those inside the stream.
+        let mut tags = HashMap::new();
+        let mut container = probed.metadata;
+        for revision in [
+            container.get().and_then(|m| m.current().cloned()),
+            format.metadata().current().cloned(),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            tags.extend(revision.tags().iter().map(tag_entry));
+        }
+        let track = format
+            .tracks()
+            .iter()
+            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or_else(|| invalid_data("no audio track"))?;
+        let params = &track.codec_params;
+        let channels = params
+            .channels
+            .map(|c| c.count() as u16)
+            .ok_or_else(|| invalid_data("unknown channel count"))?;
+        let sample_rate = params
+            .sample_rate
+            .ok_or_else(|| invalid_data("unknown sample rate"))?;
+        let codecs = symphonia::default::get_codecs();
+        let decoder = codecs
+            .make(params, &DecoderOptions::default())
+            .map_err(into_io)?;
+        let info = AudioFileInfo {
+            codec: codecs
+                .get_codec(params.codec)
+                .map(|c| c.short_name.to_string())
+                .unwrap_or_default(),
+            channels,
+            sample_rate,
+            frames: params.n_frames.map(|n| n as i64),
+            duration: params.n_frames.map(|n| n as f64 / sample_rate as f64),
+            tags,
+        };
+        Ok(FileDecoder {
+            track_id: track.id,
+            format,
+            decoder,
+            channels,
+            sample_rate,
+            info,
+            samples: None,
+            seek_target: 0,
+        })
+    }
+
+    pub(crate) fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    pub(crate) fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub(crate) fn info(&self) -> &AudioFileInfo {
+        &self.info
+    }
+
+    /// Appends the next packet's samples to `out`; returns `false` at the end of the file.
+    pub(crate) fn next_block(&mut self, out: &mut Vec<f32>) -> io::Result<bool> {
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(DecodeError::IoError(err)) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Ok(false)
+                }
+                Err(err) => return Err(into_io(err)),
+            };
+            if packet.track_id() != self.track_id || packet.ts() + packet.dur() <= self.seek_target
+            {
+                continue;
+            }
+            let skip = self.seek_target.saturating_sub(packet.ts()) as usize;
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                // A corrupt packet is skipped rather than ending playback.
+                Err(DecodeError::DecodeError(_)) => continue,
+                Err(err) => return Err(into_io(err)),
+            };
+            let needed = decoded.capacity() as u64;
+            let samples = match &mut self.samples {
+                Some(samples) if samples.capacity() as u64 >= needed => samples,
+                slot => slot.insert(SampleBuffer::new(needed, *decoded.spec())),
+            };
+            samples.copy_interleaved_ref(decoded);
+            let skip = (skip * self.channels as usize).min(samples.len());
+            out.extend_from_slice(&samples.samples()[skip..]);
+            return Ok(true);
+        }
+    }
+
+    /// Moves to `seconds` from the start of the file.
+    pub(crate) fn seek(&mut self, seconds: f64) -> io::Result<()> {
+        let seeked = self
+            .format
+            .seek(
+                SeekMode::Accurate,
+                SeekTo::Time {
+                    time: Time::from(seconds.max(0.0)),
+                    track_id: Some(self.track_id),
+                },
+            )
+            .map_err(into_io)?;
+        self.seek_target = seeked.required_ts;
+        self.decoder.reset();
+        Ok(())
+    }
+}
+
+/// Common tags get stable camelCase names whatever the container calls them.
+fn tag_entry(tag: &Tag) -> (String, String) {
+    let key = match tag.std_key {
+        Some(StandardTagKey::TrackTitle) => "title",
+        Some(StandardTagKey::Artist) => "artist",
+        Some(StandardTagKey::Album) => "album",
+        Some(StandardTagKey::AlbumArtist) => "albumArtist",
+        Some(StandardTagKey::Genre) => "genre",
+        Some(StandardTagKey::Date) => "date",
+        Some(StandardTagKey::TrackNumber) => "trackNumber",
+        Some(StandardTagKey::Comment) => "comment",
+        _ => &tag.key,
+    };
+    (key.to_string(), tag.value.to_string())
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn into_io(err: DecodeError) -> io::Error {
+    match err {
+        DecodeError::IoError(err) => err,
+        DecodeError::Unsupported(what) => {
+            io::Error::new(io::ErrorKind::Unsupported, format!("unsupported {}", what))
+        }
+        err => io::Error::new(io::ErrorKind::InvalidData, err.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wav::{WavFormat, WavWriter};
+
+    #[test]
+    fn test_decodes_wav_written_by_writer() {
+        let path = std::env::temp_dir().join("cpal-napi-decode.wav");
+        let mut writer = WavWriter::create(&path, 2, 22050, WavFormat::Int16).unwrap();
+        let ramp: Vec<f32> = (0..2000).map(|i| (i % 100) as f32 / 200.0).collect();
+        writer.write(&ramp).unwrap();
+        writer.finalize().unwrap();
+
+        let mut decoder = FileDecoder::open(&path).unwrap();
+        assert_eq!(decoder.channels(), 2);
+        assert_eq!(decoder.sample_rate(), 22050);
+        assert_eq!(decoder.info().codec, "pcm_s16le");
+        assert_eq!(decoder.info().frames, Some(1000));
+        let mut samples = Vec::new();
+        while decoder.next_block(&mut samples).unwrap() {}
+        assert_eq!(samples.len(), ramp.len());
+        assert!((samples[51] - ramp[51]).abs() < 1e-3);
+
+        decoder.seek(0.0).unwrap();
+        let mut again = Vec::new();
+        assert!(decoder.next_block(&mut again).unwrap());
+        assert!((again[51] - ramp[51]).abs() < 1e-3);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
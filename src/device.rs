@@ -1,6 +1,24 @@
 use crate::buffer::AudioBuffer;
-use crate::config::{BufferSize, StreamConfig, SupportedStreamConfig};
-use crate::stream::AudioStream;
+use crate::callback::{
+    CallbackCapturer, CallbackProcessor, CallbackRenderer, CaptureArgs, DuplexArgs,
+    OutputCallbackOptions, RenderArgs,
+};
+use crate::channels::ChannelMapping;
+use crate::config::{
+    find_config, supports_config, BufferSize, ConfigRequest, ConfigStrategy, ConfigTarget,
+    StreamConfig, SupportedStreamConfig,
+};
+use crate::decoder::FileDecoder;
+use crate::device_description::{DeviceDirection, DeviceFilter};
+use crate::duplex::DuplexStreamOptions;
+use crate::encoding::{chunk_sink, file_sink, AudioEncoding};
+use crate::error::{cpal_error, io_error, napi_error, ErrorCode};
+use crate::player::{FilePlayer, PlayFileOptions};
+use crate::recorder::{FileRecorder, SampleSink};
+use crate::resampler::{PullResampler, PushResampler};
+use crate::sample;
+use crate::stream::{AudioStream, StreamStatus};
+use crate::wav::WavFormat;
 use cpal::traits::DeviceTrait;
 use napi::bindgen_prelude::*;
 use napi_derive::napi;
@@ -8,13 +26,32 @@ use napi_derive::napi;
 #[napi]
 pub struct AudioDevice {
     pub(crate) inner: cpal::Device,
+    /// False for offline devices, whose callbacks may wait for JavaScript.
+    pub(crate) realtime: bool,
 }
 
 #[napi(object)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DeviceId {
     pub id: String,
 }
 
+/// Seconds of audio the recorder can hold while the writer thread catches up.
+const RECORD_QUEUE_SECONDS: usize = 4;
+/// Seconds of audio held for a JavaScript input callback that has fallen behind.
+const CAPTURE_QUEUE_SECONDS: usize = 1;
+
+#[napi(object)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RecordOptions {
+    /// Picked from the file extension when omitted: `.flac`, `.opus`/`.ogg`, otherwise WAV.
+    pub encoding: Option<AudioEncoding>,
+    /// Sample encoding in WAV files (`Int16` by default); FLAC keeps 24 bits for wider ones.
+    pub format: Option<WavFormat>,
+    /// Opus bitrate in bits per second; left to the encoder when omitted.
+    pub bitrate: Option<u32>,
+}
+
 #[napi(object)]
 pub struct Data {
     pub sample_format: crate::types::SampleFormat,
@@ -23,89 +60,123 @@ pub struct Data {
 #[napi]
 impl AudioDevice {
     #[napi]
-    pub fn id(&self) -> Result<DeviceId> {
+    pub fn id(&self) -> Result<DeviceId, ErrorCode> {
         Ok(DeviceId {
             id: self
                 .inner
                 .id()
-                .map_err(|e| Error::from_reason(format!("Failed to get device id: {}", e)))?
+                .map_err(cpal_error("Failed to get device id"))?
                 .to_string(),
         })
     }
 
     #[napi]
-    pub fn description(&self) -> Result<crate::device_description::DeviceDescription> {
+    pub fn description(&self) -> Result<crate::device_description::DeviceDescription, ErrorCode> {
         let desc = self
             .inner
             .description()
-            .map_err(|e| Error::from_reason(format!("Failed to get device description: {}", e)))?;
-        Ok(crate::device_description::DeviceDescription {
-            name: desc.name().to_string(),
-            direction: match desc.direction() {
-                cpal::DeviceDirection::Input => crate::device_description::DeviceDirection::Input,
-                cpal::DeviceDirection::Output => crate::device_description::DeviceDirection::Output,
-                _ => crate::device_description::DeviceDirection::Output,
-            },
-            device_type: crate::device_description::DeviceType::Other,
-            interface_type: crate::device_description::InterfaceType::Other,
-        })
+            .map_err(cpal_error("Failed to get device description"))?;
+        Ok(crate::device_description::DeviceDescription::from_cpal(
+            &desc,
+            self.inner.id().ok().map(|id| id.0),
+            self.inner.supports_input(),
+            self.inner.supports_output(),
+        ))
     }
 
     #[napi]
-    pub fn name(&self) -> Result<String> {
+    pub fn name(&self) -> Result<String, ErrorCode> {
         Ok(self
             .inner
             .description()
-            .map_err(|e| Error::from_reason(format!("Failed to get name: {}", e)))?
+            .map_err(cpal_error("Failed to get name"))?
             .name()
             .to_string())
     }
 
     #[napi]
-    pub fn default_output_config(&self) -> Result<StreamConfig> {
-        let config = self.inner.default_output_config().map_err(|e| {
-            Error::from_reason(format!("Failed to get default output config: {}", e))
-        })?;
-        Ok(StreamConfig {
-            channels: config.channels(),
-            sample_rate: config.sample_rate(),
-            buffer_size: BufferSize::Default,
-        })
+    pub fn default_output_config(&self) -> Result<StreamConfig, ErrorCode> {
+        let config = self
+            .inner
+            .default_output_config()
+            .map_err(cpal_error("Failed to get default output config"))?;
+        Ok(config.into())
     }
 
     #[napi]
-    pub fn default_input_config(&self) -> Result<StreamConfig> {
-        let config = self.inner.default_input_config().map_err(|e| {
-            Error::from_reason(format!("Failed to get default input config: {}", e))
-        })?;
-        Ok(StreamConfig {
-            channels: config.channels(),
-            sample_rate: config.sample_rate(),
-            buffer_size: BufferSize::Default,
-        })
+    pub fn default_input_config(&self) -> Result<StreamConfig, ErrorCode> {
+        let config = self
+            .inner
+            .default_input_config()
+            .map_err(cpal_error("Failed to get default input config"))?;
+        Ok(config.into())
     }
 
     #[napi]
-    pub fn supported_output_configs(&self) -> Result<Vec<SupportedStreamConfig>> {
-        let configs = self.inner.supported_output_configs().map_err(|e| {
-            Error::from_reason(format!("Failed to get supported output configs: {}", e))
-        })?;
+    pub fn supported_output_configs(&self) -> Result<Vec<SupportedStreamConfig>, ErrorCode> {
+        let configs = self
+            .inner
+            .supported_output_configs()
+            .map_err(cpal_error("Failed to get supported output configs"))?;
         Ok(configs.map(|c| c.into()).collect())
     }
 
     #[napi]
-    pub fn supported_input_configs(&self) -> Result<Vec<SupportedStreamConfig>> {
-        let configs = self.inner.supported_input_configs().map_err(|e| {
-            Error::from_reason(format!("Failed to get supported input configs: {}", e))
-        })?;
+    pub fn supported_input_configs(&self) -> Result<Vec<SupportedStreamConfig>, ErrorCode> {
+        let configs = self
+            .inner
+            .supported_input_configs()
+            .map_err(cpal_error("Failed to get supported input configs"))?;
         Ok(configs.map(|c| c.into()).collect())
     }
 
+    /// The supported output config closest to `request` (the default config's values for
+    /// omitted fields), matched by `strategy`, `Nearest` by default.
+    #[napi]
+    pub fn find_output_config(
+        &self,
+        request: Option<ConfigRequest>,
+        strategy: Option<ConfigStrategy>,
+    ) -> Result<StreamConfig, ErrorCode> {
+        self.find_config(
+            request.unwrap_or_default(),
+            strategy.unwrap_or_default(),
+            false,
+        )
+    }
+
     #[napi]
-    pub fn create_beep_stream(&self) -> Result<AudioStream> {
-        let config = self.inner.default_output_config().map_err(|e| {
-            Error::from_reason(format!("Failed to get default output config: {}", e))
-        })?;
+    pub fn find_input_config(
+        &self,
+        request: Option<ConfigRequest>,
+        strategy: Option<ConfigStrategy>,
+    ) -> Result<StreamConfig, ErrorCode> {
+        self.find_config(
+            request.unwrap_or_default(),
+            strategy.unwrap_or_default(),
+            true,
+        )
+    }
+
+    /// Whether the device can open `config` as is, without resampling.
+    #[napi]
+    pub fn is_output_config_supported(&self, config: StreamConfig) -> bool {
+        let sample_format = self.output_sample_format(&config);
+        supports_config(&self.supported_ranges(false), &config, sample_format)
+    }
+
+    #[napi]
+    pub fn is_input_config_supported(&self, config: StreamConfig) -> bool {
+        let sample_format = self.input_sample_format(&config);
+        supports_config(&self.supported_ranges(true), &config, sample_format)
+    }
+
+    #[napi]
+    pub fn create_beep_stream(&self) -> Result<AudioStream, ErrorCode> {
+        let config = self
+            .inner
+            .default_output_config()
+            .map_err(cpal_error("Failed to get default output config"))?;
 
         let sample_format = config.sample_format();
         let config_inner: cpal::StreamConfig = config.into();
@@ -118,53 +189,27 @@ impl AudioDevice {
             (sample_clock * 440.0 * 2.0 * std::f32::consts::PI / sample_rate).sin()
         };
 
-        let err_fn = |err| eprintln!("an error occurred on stream: {}", err);
+        let status = StreamStatus::new();
+        let err_fn = status.error_handler();
 
-        let stream = match sample_format {
-            cpal::SampleFormat::F32 => self.inner.build_output_stream(
-                &config_inner,
-                move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                    for frame in data.chunks_mut(channels) {
-                        let value = next_value();
-                        for sample in frame.iter_mut() {
-                            *sample = value;
-                        }
-                    }
-                },
-                err_fn,
-                None,
-            ),
-            cpal::SampleFormat::I16 => self.inner.build_output_stream(
-                &config_inner,
-                move |data: &mut [i16], _: &cpal::OutputCallbackInfo| {
-                    for frame in data.chunks_mut(channels) {
-                        let value = (next_value() * i16::MAX as f32) as i16;
-                        for sample in frame.iter_mut() {
-                            *sample = value;
-                        }
-                    }
-                },
-                err_fn,
-                None,
-            ),
-            cpal::SampleFormat::U16 => self.inner.build_output_stream(
-                &config_inner,
-                move |data: &mut [u16], _: &cpal::OutputCallbackInfo| {
-                    for frame in data.chunks_mut(channels) {
-                        let value = ((next_value() * 0.5 + 0.5) * u16::MAX as f32) as u16;
-                        for sample in frame.iter_mut() {
-                            *sample = value;
-                        }
+        let stream = sample::build_output_stream(
+            &self.inner,
+            &config_inner,
+            sample_format,
+            Some(status.clone()),
+            move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                for frame in data.chunks_mut(channels) {
+                    let value = next_value();
+                    for sample in frame.iter_mut() {
+                        *sample = value;
                     }
-                },
-                err_fn,
-                None,
-            ),
-            _ => return Err(Error::from_reason("Unsupported sample format")),
-        }
-        .map_err(|e| Error::from_reason(format!("Failed to build stream: {}", e)))?;
+                }
+            },
+            err_fn,
+        )
+        .map_err(cpal_error("Failed to build stream"))?;
 
-        Ok(AudioStream::new(stream))
+        Ok(AudioStream::new(stream, status))
     }
 
     #[napi]
@@ -172,37 +217,118 @@ impl AudioDevice {
         &self,
         config: StreamConfig,
         buffer: &AudioBuffer,
-    ) -> Result<AudioStream> {
-        let cpal_config = cpal::StreamConfig {
-            channels: config.channels,
-            sample_rate: config.sample_rate,
-            buffer_size: config.buffer_size.into(),
-        };
+        mapping: Option<ChannelMapping>,
+    ) -> Result<AudioStream, ErrorCode> {
+        let sample_format = self.output_sample_format(&config);
+        let cpal_config = self.negotiate(&config, sample_format, false);
 
-        let channels = config.channels as usize;
+        let channels = config.channels.max(1) as usize;
         let shared_buffer = buffer.inner.clone();
+        let watermarks = buffer.watermarks.clone();
+        let mapping = mapping.unwrap_or_default();
+        mapping.validate(buffer.channels)?;
+        let mut buffer_frame = vec![0.0; buffer.channels as usize];
+        let buffer_rate = buffer.sample_rate.unwrap_or(config.sample_rate);
+        let mut bridge = (buffer_rate != cpal_config.sample_rate).then(|| {
+            PullResampler::new(
+                buffer.channels,
+                buffer_rate,
+                cpal_config.sample_rate,
+                config.resample_quality.unwrap_or_default(),
+                sample::max_chunk_frames(cpal_config.channels),
+            )
+        });
+        let mut converted =
+            vec![0.0; sample::max_chunk_frames(cpal_config.channels) * buffer_frame.len()];
 
-        let err_fn = |err| eprintln!("an error occurred on stream: {}", err);
+        let status = StreamStatus::new();
+        let err_fn = status.error_handler();
 
-        let stream = self
-            .inner
-            .build_output_stream(
-                &cpal_config,
-                move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                    let mut buffer = shared_buffer.lock().unwrap();
-                    for frame in data.chunks_mut(channels) {
-                        let value = buffer.pop_front().unwrap_or(0.0);
-                        for sample in frame.iter_mut() {
-                            *sample = value;
+        let stream = sample::build_output_stream(
+            &self.inner,
+            &cpal_config,
+            sample_format,
+            Some(status.clone()),
+            move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                match &mut bridge {
+                    None if buffer_frame.len() == channels => {
+                        shared_buffer.pop_or_silence(data);
+                    }
+                    None => {
+                        for frame in data.chunks_mut(channels) {
+                            shared_buffer.pop_or_silence(&mut buffer_frame);
+                            mapping.map_frame(&buffer_frame, frame);
+                        }
+                    }
+                    Some(bridge) => {
+                        let converted =
+                            &mut converted[..data.len() / channels * buffer_frame.len()];
+                        bridge.fill(converted, |input| {
+                            shared_buffer.pop_or_silence(input);
+                        });
+                        for (source, frame) in converted
+                            .chunks(buffer_frame.len())
+                            .zip(data.chunks_mut(channels))
+                        {
+                            mapping.map_frame(source, frame);
                         }
                     }
-                },
-                err_fn,
-                None,
+                }
+                watermarks.check(shared_buffer.len());
+            },
+            err_fn,
+        )
+        .map_err(cpal_error("Failed to build stream"))?;
+
+        Ok(AudioStream::new(stream, status))
+    }
+
+    #[napi(
+        ts_args_type = "config: StreamConfig, callback: (frames: number, info: OutputCallbackInfo) => Float32Array, options?: OutputCallbackOptions"
+    )]
+    pub fn create_output_stream_with_callback(
+        &self,
+        config: StreamConfig,
+        callback: Function<'_, RenderArgs, Float32Array>,
+        options: Option<OutputCallbackOptions>,
+    ) -> Result<AudioStream, ErrorCode> {
+        let sample_format = self.output_sample_format(&config);
+        let cpal_config = self.negotiate(&config, sample_format, false);
+        let status = StreamStatus::new();
+        let renderer = CallbackRenderer::new(
+            &callback,
+            config.channels,
+            options,
+            self.realtime,
+            status.clone(),
+        )
+        .map_err(napi_error)?;
+        let mut bridge = (config.sample_rate != cpal_config.sample_rate).then(|| {
+            PullResampler::new(
+                config.channels,
+                config.sample_rate,
+                cpal_config.sample_rate,
+                config.resample_quality.unwrap_or_default(),
+                sample::max_chunk_frames(cpal_config.channels),
             )
-            .map_err(|e| Error::from_reason(format!("Failed to build stream: {}", e)))?;
+        });
 
-        Ok(AudioStream::new(stream))
+        let err_fn = status.error_handler();
+
+        let stream = sample::build_output_stream(
+            &self.inner,
+            &cpal_config,
+            sample_format,
+            Some(status.clone()),
+            move |data: &mut [f32], info: &cpal::OutputCallbackInfo| match &mut bridge {
+                None => renderer.render(data, info),
+                Some(bridge) => bridge.fill(data, |input| renderer.render(input, info)),
+            },
+            err_fn,
+        )
+        .map_err(cpal_error("Failed to build stream"))?;
+
+        Ok(AudioStream::new(stream, status))
     }
 
     #[napi]
@@ -210,35 +336,412 @@ impl AudioDevice {
         &self,
         config: StreamConfig,
         buffer: &AudioBuffer,
-    ) -> Result<AudioStream> {
-        let cpal_config = cpal::StreamConfig {
-            channels: config.channels,
-            sample_rate: config.sample_rate,
-            buffer_size: config.buffer_size.into(),
-        };
+        mapping: Option<ChannelMapping>,
+    ) -> Result<AudioStream, ErrorCode> {
+        let sample_format = self.input_sample_format(&config);
+        let cpal_config = self.negotiate(&config, sample_format, true);
 
-        let channels = config.channels as usize;
+        let channels = config.channels.max(1) as usize;
         let shared_buffer = buffer.inner.clone();
+        let watermarks = buffer.watermarks.clone();
+        let mapping = mapping.unwrap_or_default();
+        mapping.validate(config.channels)?;
+        let mut buffer_frame = vec![0.0; buffer.channels as usize];
+        let mut mapped =
+            Vec::with_capacity(sample::max_chunk_frames(cpal_config.channels) * buffer_frame.len());
+        let mut bridge = PushResampler::new(
+            buffer.channels,
+            cpal_config.sample_rate,
+            buffer.sample_rate.unwrap_or(config.sample_rate),
+            config.resample_quality.unwrap_or_default(),
+            sample::max_chunk_frames(cpal_config.channels),
+        );
 
-        let err_fn = |err| eprintln!("an error occurred on stream: {}", err);
+        let status = StreamStatus::new();
+        let err_fn = status.error_handler();
 
-        let stream = self
-            .inner
-            .build_input_stream(
-                &cpal_config,
-                move |data: &[f32], _: &cpal::InputCallbackInfo| {
-                    let mut buffer = shared_buffer.lock().unwrap();
+        let stream = sample::build_input_stream(
+            &self.inner,
+            &cpal_config,
+            sample_format,
+            Some(status.clone()),
+            move |data: &[f32], _: &cpal::InputCallbackInfo| {
+                let data = if buffer_frame.len() == channels {
+                    data
+                } else {
+                    mapped.clear();
                     for frame in data.chunks(channels) {
-                        if let Some(sample) = frame.first() {
-                            buffer.push_back(*sample);
-                        }
+                        mapping.map_frame(frame, &mut buffer_frame);
+                        mapped.extend_from_slice(&buffer_frame);
                     }
-                },
-                err_fn,
-                None,
+                    &mapped
+                };
+                shared_buffer.push_slice(bridge.process(data));
+                watermarks.check(shared_buffer.len());
+            },
+            err_fn,
+        )
+        .map_err(cpal_error("Failed to build input stream"))?;
+
+        Ok(AudioStream::new(stream, status))
+    }
+
+    #[napi(
+        ts_args_type = "config: StreamConfig, callback: (data: Float32Array, info: InputCallbackInfo) => void"
+    )]
+    pub fn create_input_stream_with_callback(
+        &self,
+        config: StreamConfig,
+        callback: Function<'_, CaptureArgs, ()>,
+    ) -> Result<AudioStream, ErrorCode> {
+        let sample_format = self.input_sample_format(&config);
+        let cpal_config = self.negotiate(&config, sample_format, true);
+        let capacity =
+            config.sample_rate as usize * config.channels as usize * CAPTURE_QUEUE_SECONDS;
+        let capturer = CallbackCapturer::new(&callback, capacity).map_err(napi_error)?;
+        let mut bridge = PushResampler::new(
+            config.channels,
+            cpal_config.sample_rate,
+            config.sample_rate,
+            config.resample_quality.unwrap_or_default(),
+            sample::max_chunk_frames(cpal_config.channels),
+        );
+
+        let status = StreamStatus::new();
+        let err_fn = status.error_handler();
+
+        let stream = sample::build_input_stream(
+            &self.inner,
+            &cpal_config,
+            sample_format,
+            Some(status.clone()),
+            move |data: &[f32], info: &cpal::InputCallbackInfo| {
+                let data = bridge.process(data);
+                if !data.is_empty() {
+                    capturer.capture(data, info);
+                }
+            },
+            err_fn,
+        )
+        .map_err(cpal_error("Failed to build input stream"))?;
+
+        Ok(AudioStream::new(stream, status))
+    }
+
+    /// Plays this device from `callback`, which receives each block `input` captures and
+    /// returns as many frames of output.
+    #[napi(
+        ts_args_type = "input: AudioDevice, config: StreamConfig, callback: (input: Float32Array, info: OutputCallbackInfo) => Float32Array, options?: DuplexStreamOptions"
+    )]
+    pub fn create_duplex_stream(
+        &self,
+        input: &AudioDevice,
+        config: StreamConfig,
+        callback: Function<'_, DuplexArgs, Float32Array>,
+        options: Option<DuplexStreamOptions>,
+    ) -> Result<AudioStream, ErrorCode> {
+        if !self.realtime {
+            return Err(Error::new(
+                ErrorCode::InvalidArgument,
+                "Duplex streams need a realtime output device",
+            ));
+        }
+        let options = options.unwrap_or_default();
+        let status = StreamStatus::new();
+        let processor = CallbackProcessor::new(
+            &callback,
+            options.input_channels.unwrap_or(config.channels),
+            config.channels,
+            &options,
+            status.clone(),
+        )
+        .map_err(napi_error)?;
+        self.build_duplex_stream_with_status(input, &config, options, processor, status)
+    }
+
+    /// Records the device input into a WAV, FLAC or Ogg Opus file at `path` until the
+    /// stream is stopped.
+    #[napi]
+    pub fn record_to_file(
+        &self,
+        path: String,
+        config: StreamConfig,
+        options: Option<RecordOptions>,
+    ) -> Result<AudioStream, ErrorCode> {
+        let options = options.unwrap_or_default();
+        let sink = file_sink(&path, config.channels, config.sample_rate, &options)?;
+        self.record_into(config, sink, format!("Failed to finalize {}", path))
+    }
+
+    /// Encodes the device input to FLAC or Ogg Opus and hands the bytes to `callback` as
+    /// they are produced; concatenated, the chunks form a complete file.
+    #[napi(
+        ts_args_type = "config: StreamConfig, callback: (chunk: Buffer) => void, options?: RecordOptions"
+    )]
+    pub fn create_encoded_input_stream(
+        &self,
+        config: StreamConfig,
+        callback: Function<'_, Buffer, ()>,
+        options: Option<RecordOptions>,
+    ) -> Result<AudioStream, ErrorCode> {
+        let callback = callback
+            .build_threadsafe_function()
+            .callee_handled::<false>()
+            .weak::<true>()
+            .build()
+            .map_err(napi_error)?;
+        let options = options.unwrap_or_default();
+        let sink = chunk_sink(callback, config.channels, config.sample_rate, &options)?;
+        self.record_into(config, sink, "Failed to finish encoding".to_string())
+    }
+
+    /// Plays a WAV, AIFF, FLAC, Ogg Vorbis or MP3 file, decoded and converted to the
+    /// stream's format on a background thread.
+    #[napi]
+    pub fn play_file(
+        &self,
+        path: String,
+        options: Option<PlayFileOptions>,
+    ) -> Result<AudioStream, ErrorCode> {
+        let options = options.unwrap_or_default();
+        let config = match options.config {
+            Some(config) => config,
+            None => self.default_output_config()?,
+        };
+        let sample_format = self.output_sample_format(&config);
+        let cpal_config = self.negotiate(&config, sample_format, false);
+        let decoder =
+            FileDecoder::open(&path).map_err(io_error(format!("Failed to open {}", path)))?;
+        if let Some(mapping) = options.mapping {
+            mapping.validate(decoder.channels())?;
+        }
+        let status = StreamStatus::new();
+        let player = FilePlayer::spawn(
+            decoder,
+            config.channels,
+            cpal_config.sample_rate,
+            config.resample_quality.unwrap_or_default(),
+            options,
+            status.clone(),
+        )
+        .map_err(io_error(format!("Failed to seek in {}", path)))?;
+        let queue = player.queue();
+
+        let realtime = self.realtime;
+        let err_fn = status.error_handler();
+
+        let stream = sample::build_output_stream(
+            &self.inner,
+            &cpal_config,
+            sample_format,
+            Some(status.clone()),
+            move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                queue.fill(data, realtime);
+            },
+            err_fn,
+        )
+        .map_err(cpal_error("Failed to build stream"))?;
+
+        let playback = player.queue();
+        Ok(AudioStream::new(stream, status)
+            .with_playback(playback)
+            .with_finisher(Box::new(move || {
+                drop(player);
+                Ok(())
+            })))
+    }
+}
+
+impl AudioDevice {
+    /// Input stream feeding `sink` from a writer thread; closing the stream finalizes it.
+    fn record_into(
+        &self,
+        config: StreamConfig,
+        sink: Box<dyn SampleSink>,
+        context: String,
+    ) -> Result<AudioStream, ErrorCode> {
+        let sample_format = self.input_sample_format(&config);
+        let cpal_config = self.negotiate(&config, sample_format, true);
+        let capacity =
+            config.sample_rate as usize * config.channels as usize * RECORD_QUEUE_SECONDS;
+        let recorder = FileRecorder::spawn(sink, capacity);
+        let queue = recorder.ring();
+        let mut bridge = PushResampler::new(
+            config.channels,
+            cpal_config.sample_rate,
+            config.sample_rate,
+            config.resample_quality.unwrap_or_default(),
+            sample::max_chunk_frames(cpal_config.channels),
+        );
+
+        let status = StreamStatus::new();
+        let err_fn = status.error_handler();
+
+        let stream = sample::build_input_stream(
+            &self.inner,
+            &cpal_config,
+            sample_format,
+            Some(status.clone()),
+            move |data: &[f32], _: &cpal::InputCallbackInfo| {
+                queue.push_slice(bridge.process(data));
+            },
+            err_fn,
+        )
+        .map_err(cpal_error("Failed to build input stream"))?;
+
+        Ok(
+            AudioStream::new(stream, status).with_finisher(Box::new(move || {
+                recorder.finish().map_err(io_error(context))
+            })),
+        )
+    }
+
+    /// `config` as the device will run it: unchanged when the device supports its rate
+    /// (or cannot say), otherwise at the closest rate it supports for the same channels
+    /// and format. Streams resample between the two.
+    pub(crate) fn negotiate(
+        &self,
+        config: &StreamConfig,
+        sample_format: cpal::SampleFormat,
+        input: bool,
+    ) -> cpal::StreamConfig {
+        let mut cpal_config: cpal::StreamConfig = (*config).into();
+        let ranges: Vec<_> = self
+            .supported_ranges(input)
+            .into_iter()
+            .filter(|r| r.channels() == config.channels && r.sample_format() == sample_format)
+            .collect();
+        let supported = |r: &cpal::SupportedStreamConfigRange| {
+            (r.min_sample_rate()..=r.max_sample_rate()).contains(&config.sample_rate)
+        };
+        if ranges.is_empty() || ranges.iter().any(supported) {
+            return cpal_config;
+        }
+        if let Some(rate) = ranges
+            .iter()
+            .map(|r| {
+                config
+                    .sample_rate
+                    .clamp(r.min_sample_rate(), r.max_sample_rate())
+            })
+            .min_by_key(|rate| rate.abs_diff(config.sample_rate))
+        {
+            cpal_config.sample_rate = rate;
+        }
+        cpal_config
+    }
+
+    /// Whether the device meets every condition in `filter`.
+    pub(crate) fn matches(&self, filter: &DeviceFilter) -> Result<bool, ErrorCode> {
+        let (input, output) = match filter.direction {
+            Some(DeviceDirection::Input) => (true, false),
+            Some(DeviceDirection::Output) => (false, true),
+            Some(DeviceDirection::Duplex) | None => (true, true),
+        };
+        if filter.direction.is_some()
+            && ((input && !self.inner.supports_input())
+                || (output && !self.inner.supports_output()))
+        {
+            return Ok(false);
+        }
+        if filter.device_type.is_some() || filter.interface_type.is_some() {
+            let description = self.description()?;
+            if filter
+                .device_type
+                .is_some_and(|t| t != description.device_type)
+                || filter
+                    .interface_type
+                    .is_some_and(|t| t != description.interface_type)
+            {
+                return Ok(false);
+            }
+        }
+        if filter.min_channels.is_none() && filter.sample_rate.is_none() {
+            return Ok(true);
+        }
+        let satisfied = |input: bool| {
+            self.supported_ranges(input).iter().any(|range| {
+                filter.min_channels.is_none_or(|c| range.channels() >= c)
+                    && filter.sample_rate.is_none_or(|rate| {
+                        (range.min_sample_rate()..=range.max_sample_rate()).contains(&rate)
+                    })
+            })
+        };
+        Ok(match filter.direction {
+            // Without a direction either side may satisfy the config conditions.
+            None => satisfied(true) || satisfied(false),
+            Some(_) => (!input || satisfied(true)) && (!output || satisfied(false)),
+        })
+    }
+
+    fn supported_ranges(&self, input: bool) -> Vec<cpal::SupportedStreamConfigRange> {
+        if input {
+            self.inner.supported_input_configs().map(|c| c.collect())
+        } else {
+            self.inner.supported_output_configs().map(|c| c.collect())
+        }
+        .unwrap_or_default()
+    }
+
+    fn find_config(
+        &self,
+        request: ConfigRequest,
+        strategy: ConfigStrategy,
+        input: bool,
+    ) -> Result<StreamConfig, ErrorCode> {
+        let direction = if input { "input" } else { "output" };
+        let ranges = self.supported_ranges(input);
+        let default = if input {
+            self.inner.default_input_config()
+        } else {
+            self.inner.default_output_config()
+        };
+        let fallback = default
+            .ok()
+            .or_else(|| ranges.first().map(|r| r.with_max_sample_rate()))
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorCode::StreamConfigNotSupported,
+                    format!("Device has no {} configs", direction),
+                )
+            })?;
+        let target = ConfigTarget {
+            channels: request.channels.unwrap_or(fallback.channels()),
+            sample_rate: request.sample_rate.unwrap_or(fallback.sample_rate()),
+            sample_format: request
+                .sample_format
+                .map(Into::into)
+                .unwrap_or(fallback.sample_format()),
+            buffer_size: request.buffer_size.unwrap_or(BufferSize::Default),
+        };
+        find_config(&ranges, &target, strategy).ok_or_else(|| {
+            Error::new(
+                ErrorCode::StreamConfigNotSupported,
+                format!("No supported {} config matches the request", direction),
             )
-            .map_err(|e| Error::from_reason(format!("Failed to build input stream: {}", e)))?;
+        })
+    }
 
-        Ok(AudioStream::new(stream))
+    pub(crate) fn output_sample_format(&self, config: &StreamConfig) -> cpal::SampleFormat {
+        match config.sample_format {
+            Some(format) => format.into(),
+            None => self
+                .inner
+                .default_output_config()
+                .map(|c| c.sample_format())
+                .unwrap_or(cpal::SampleFormat::F32),
+        }
+    }
+
+    /// The format requested in `config`, falling back to the device's default input format.
+    pub(crate) fn input_sample_format(&self, config: &StreamConfig) -> cpal::SampleFormat {
+        match config.sample_format {
+            Some(format) => format.into(),
+            None => self
+                .inner
+                .default_input_config()
+                .map(|c| c.sample_format())
+                .unwrap_or(cpal::SampleFormat::F32),
+        }
     }
 }
@@ -5,6 +5,8 @@ use napi_derive::napi;
 pub enum DeviceDirection {
     Input,
     Output,
+    /// Both input and output.
+    Duplex,
 }
 
 #[napi]
@@ -35,8 +37,128 @@ pub enum InterfaceType {
 pub struct DeviceDescription {
     pub name: String,
     pub direction: DeviceDirection,
+    /// How the device is attached, from the backend when it says, else guessed from its names.
     pub device_type: DeviceType,
+    /// The audio API the device belongs to.
     pub interface_type: InterfaceType,
+    /// Backend driver or PCM name, e.g. `hw:CARD=PCH,DEV=0` on ALSA.
+    pub driver: Option<String>,
+    pub manufacturer: Option<String>,
+    /// Bus or network address, where the backend knows it.
+    pub address: Option<String>,
+    /// Further description lines from the backend, such as ALSA's card and PCM names.
+    pub extended: Vec<String>,
+}
+
+/// ALSA PCM plugins that route audio through software rather than to a card.
+const SOFTWARE_PCMS: &[&str] = &[
+    "default", "pulse", "pipewire", "jack", "null", "dmix", "dsnoop", "oss", "speex", "upmix",
+    "vdownmix",
+];
+
+impl DeviceDescription {
+    /// Converts cpal's description; `supports_input`/`supports_output` settle an unknown direction.
+    pub(crate) fn from_cpal(
+        description: &cpal::DeviceDescription,
+        host: Option<cpal::HostId>,
+        supports_input: bool,
+        supports_output: bool,
+    ) -> Self {
+        let direction = match description.direction() {
+            cpal::DeviceDirection::Input => DeviceDirection::Input,
+            cpal::DeviceDirection::Output => DeviceDirection::Output,
+            cpal::DeviceDirection::Duplex => DeviceDirection::Duplex,
+            _ => match (supports_input, supports_output) {
+                (true, true) => DeviceDirection::Duplex,
+                (true, false) => DeviceDirection::Input,
+                _ => DeviceDirection::Output,
+            },
+        };
+        DeviceDescription {
+            name: description.name().to_string(),
+            direction,
+            device_type: DeviceType::from_cpal(description),
+            interface_type: host.map_or(InterfaceType::Other, InterfaceType::from),
+            driver: description.driver().map(str::to_string),
+            manufacturer: description.manufacturer().map(str::to_string),
+            address: description.address().map(str::to_string),
+            extended: description.extended().to_vec(),
+        }
+    }
+}
+
+impl DeviceType {
+    fn from_cpal(description: &cpal::DeviceDescription) -> Self {
+        match description.interface_type() {
+            cpal::InterfaceType::BuiltIn | cpal::InterfaceType::Pci => return DeviceType::Internal,
+            cpal::InterfaceType::Usb => return DeviceType::Usb,
+            cpal::InterfaceType::Bluetooth => return DeviceType::Bluetooth,
+            cpal::InterfaceType::Network => return DeviceType::Network,
+            cpal::InterfaceType::FireWire | cpal::InterfaceType::Thunderbolt => {
+                return DeviceType::Firewire
+            }
+            cpal::InterfaceType::Virtual | cpal::InterfaceType::Aggregate => {
+                return DeviceType::Virtual
+            }
+            _ => {}
+        }
+        if description.device_type() == cpal::DeviceType::Virtual {
+            return DeviceType::Virtual;
+        }
+        // Backends like ALSA only give names, so look for telling words in them.
+        let driver = description.driver().unwrap_or_default().to_lowercase();
+        let plugin = driver.split(':').next().unwrap_or_default();
+        let text = std::iter::once(description.name())
+            .chain(description.driver())
+            .chain(description.extended().iter().map(String::as_str))
+            .collect::<Vec<_>>()
+            .join(" ")
+            .to_lowercase();
+        let has = |words: &[&str]| words.iter().any(|w| text.contains(w));
+        if has(&["bluetooth", "bluez", "a2dp"]) {
+            DeviceType::Bluetooth
+        } else if has(&["usb"]) {
+            DeviceType::Usb
+        } else if SOFTWARE_PCMS.contains(&plugin) || has(&["loopback", "virtual", "monitor of"]) {
+            DeviceType::Virtual
+        } else if has(&["firewire", "thunderbolt"]) {
+            DeviceType::Firewire
+        } else if has(&["dante", "airplay", "network", "avb"]) {
+            DeviceType::Network
+        } else if has(&["hda intel", "built-in", "internal", "pch"]) {
+            DeviceType::Internal
+        } else {
+            DeviceType::Other
+        }
+    }
+}
+
+impl From<cpal::HostId> for InterfaceType {
+    fn from(host: cpal::HostId) -> Self {
+        match host.name().to_ascii_lowercase().as_str() {
+            "alsa" => InterfaceType::Alsa,
+            "jack" => InterfaceType::Jack,
+            "wasapi" => InterfaceType::Wasapi,
+            "asio" => InterfaceType::Asio,
+            "coreaudio" => InterfaceType::CoreAudio,
+            "emscripten" => InterfaceType::Emscripten,
+            _ => InterfaceType::Other,
+        }
+    }
+}
+
+/// Conditions for `AudioHost.devicesFiltered`; every field given must hold.
+#[napi(object)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DeviceFilter {
+    /// `Input` or `Output` also match duplex devices; `Duplex` needs both.
+    pub direction: Option<DeviceDirection>,
+    /// Fewest channels a supported config must have in that direction (both for `Duplex`).
+    pub min_channels: Option<u16>,
+    /// Rate a supported config must include in that direction.
+    pub sample_rate: Option<u32>,
+    pub device_type: Option<DeviceType>,
+    pub interface_type: Option<InterfaceType>,
 }
 
 #[napi]
@@ -45,6 +167,10 @@ pub struct DeviceDescriptionBuilder {
     direction: Option<DeviceDirection>,
     device_type: Option<DeviceType>,
     interface_type: Option<InterfaceType>,
+    driver: Option<String>,
+    manufacturer: Option<String>,
+    address: Option<String>,
+    extended: Vec<String>,
 }
 
 impl Default for DeviceDescriptionBuilder {
@@ -62,6 +188,10 @@ impl DeviceDescriptionBuilder {
             direction: None,
             device_type: None,
             interface_type: None,
+            driver: None,
+            manufacturer: None,
+            address: None,
+            extended: Vec::new(),
         }
     }
 
@@ -85,6 +215,26 @@ impl DeviceDescriptionBuilder {
         self.interface_type = Some(interface_type);
     }
 
+    #[napi]
+    pub fn driver(&mut self, driver: String) {
+        self.driver = Some(driver);
+    }
+
+    #[napi]
+    pub fn manufacturer(&mut self, manufacturer: String) {
+        self.manufacturer = Some(manufacturer);
+    }
+
+    #[napi]
+    pub fn address(&mut self, address: String) {
+        self.address = Some(address);
+    }
+
+    #[napi]
+    pub fn add_extended_line(&mut self, line: String) {
+        self.extended.push(line);
+    }
+
     #[napi]
     pub fn build(&self) -> DeviceDescription {
         DeviceDescription {
@@ -92,6 +242,59 @@ impl DeviceDescriptionBuilder {
             direction: self.direction.unwrap_or(DeviceDirection::Output),
             device_type: self.device_type.unwrap_or(DeviceType::Other),
             interface_type: self.interface_type.unwrap_or(InterfaceType::Other),
+            driver: self.driver.clone(),
+            manufacturer: self.manufacturer.clone(),
+            address: self.address.clone(),
+            extended: self.extended.clone(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alsa(name: &str, pcm: &str, lines: &[&str]) -> DeviceDescription {
+        let description = cpal::DeviceDescriptionBuilder::new(name)
+            .driver(pcm)
+            .direction(cpal::DeviceDirection::Unknown)
+            .extended(lines.iter().map(|l| l.to_string()).collect())
+            .build();
+        DeviceDescription::from_cpal(&description, None, true, false)
+    }
+
+    #[test]
+    fn test_device_type_from_alsa_names() {
+        let card = alsa(
+            "HDA Intel PCH, ALC892 Analog",
+            "hw:CARD=PCH,DEV=0",
+            &["HDA Intel PCH, ALC892 Analog", "Direct hardware device"],
+        );
+        assert_eq!(card.device_type, DeviceType::Internal);
+        assert_eq!(card.direction, DeviceDirection::Input);
+        assert_eq!(card.driver.as_deref(), Some("hw:CARD=PCH,DEV=0"));
+        assert_eq!(card.extended.len(), 2);
+
+        let headset = alsa("USB Audio Device, USB Audio", "hw:CARD=Device,DEV=0", &[]);
+        assert_eq!(headset.device_type, DeviceType::Usb);
+        let pipewire = alsa("PipeWire Sound Server", "pipewire", &[]);
+        assert_eq!(pipewire.device_type, DeviceType::Virtual);
+        // `sysdefault` talks to the card directly, unlike `default`.
+        let sysdefault = alsa("Unknown card", "sysdefault:CARD=X", &[]);
+        assert_eq!(sysdefault.device_type, DeviceType::Other);
+    }
+
+    #[test]
+    fn test_backend_types_win_over_names() {
+        let description = cpal::DeviceDescriptionBuilder::new("USB thing")
+            .interface_type(cpal::InterfaceType::Bluetooth)
+            .direction(cpal::DeviceDirection::Duplex)
+            .manufacturer("Acme")
+            .build();
+        let converted = DeviceDescription::from_cpal(&description, None, false, false);
+        assert_eq!(converted.device_type, DeviceType::Bluetooth);
+        assert_eq!(converted.direction, DeviceDirection::Duplex);
+        assert_eq!(converted.interface_type, InterfaceType::Other);
+        assert_eq!(converted.manufacturer.as_deref(), Some("Acme"));
+    }
+}
@@ -0,0 +1,328 @@
+use crate::config::StreamConfig;
+use crate::device::AudioDevice;
+use crate::error::{cpal_error, ErrorCode};
+use crate::resampler::{PullResampler, PushResampler};
+use crate::ring::RingBuffer;
+use crate::sample;
+use crate::stream::{AudioStream, StreamStatus};
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+use std::sync::Arc;
+
+/// Input kept queued ahead of the output unless the options say otherwise.
+const DEFAULT_LATENCY_MS: f64 = 20.0;
+/// Read rate correction per unit of relative error in the queue level.
+const DRIFT_GAIN: f64 = 0.01;
+/// Largest read rate correction, under 9 cents of pitch.
+const MAX_CORRECTION: f64 = 0.005;
+/// Fraction of the way the read rate moves towards its new value per callback.
+const SMOOTHING: f64 = 0.05;
+/// Input queued beyond this many times the target is dropped rather than caught up on.
+const RESYNC_FACTOR: usize = 4;
+
+#[napi(object)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DuplexStreamOptions {
+    /// Channels captured from the input device; the config's channel count by default.
+    pub input_channels: Option<u16>,
+    /// Input kept queued ahead of the output to absorb callback jitter (default 20 ms).
+    pub latency_ms: Option<f64>,
+    /// Frames passed to a JavaScript callback per call (default 512).
+    pub block_frames: Option<u32>,
+    /// Blocks of silence played while the first JavaScript results are pending (default 4).
+    pub preroll_blocks: Option<u32>,
+}
+
+/// Turns captured input into the same number of output frames.
+///
+/// Runs on the output device's audio thread, so it must not block. Both slices are
+/// interleaved at the stream's rate, and `output` must be filled completely.
+pub trait DuplexProcessor: Send + 'static {
+    fn process(&mut self, input: &[f32], output: &mut [f32], info: &cpal::OutputCallbackInfo);
+}
+
+impl<F> DuplexProcessor for F
+where
+    F: FnMut(&[f32], &mut [f32], &cpal::OutputCallbackInfo) + Send + 'static,
+{
+    fn process(&mut self, input: &[f32], output: &mut [f32], info: &cpal::OutputCallbackInfo) {
+        self(input, output, info)
+    }
+}
+
+impl AudioDevice {
+    /// Plays this device from `processor`, which also receives what `input` captures.
+    ///
+    /// Each device runs its own cpal stream; captured frames wait in a queue that is read
+    /// slightly faster or slower as it fills or drains, so devices on different clocks
+    /// stay in step. The returned stream starts, pauses and closes both.
+    pub fn build_duplex_stream<P: DuplexProcessor>(
+        &self,
+        input: &AudioDevice,
+        config: &StreamConfig,
+        options: DuplexStreamOptions,
+        processor: P,
+    ) -> Result<AudioStream, ErrorCode> {
+        self.build_duplex_stream_with_status(input, config, options, processor, StreamStatus::new())
+    }
+
+    /// Like `build_duplex_stream`, reporting through a `status` the processor also holds.
+    pub(crate) fn build_duplex_stream_with_status<P: DuplexProcessor>(
+        &self,
+        input: &AudioDevice,
+        config: &StreamConfig,
+        options: DuplexStreamOptions,
+        mut processor: P,
+        status: Arc<StreamStatus>,
+    ) -> Result<AudioStream, ErrorCode> {
+        let input_config = StreamConfig {
+            channels: options.input_channels.unwrap_or(config.channels),
+            sample_format: None,
+            ..*config
+        };
+        let input_channels = input_config.channels.max(1) as usize;
+        let output_channels = config.channels.max(1) as usize;
+        let quality = config.resample_quality.unwrap_or_default();
+        let latency_ms = options
+            .latency_ms
+            .filter(|ms| *ms > 0.0)
+            .unwrap_or(DEFAULT_LATENCY_MS);
+        let mut compensator = DriftCompensator::new(
+            input_config.channels,
+            (config.sample_rate as f64 * latency_ms / 1000.0).ceil() as usize,
+        );
+        let queue = compensator.queue();
+
+        let input_format = input.input_sample_format(&input_config);
+        let input_cpal_config = input.negotiate(&input_config, input_format, true);
+        let mut capture_bridge = PushResampler::new(
+            input_config.channels,
+            input_cpal_config.sample_rate,
+            config.sample_rate,
+            quality,
+            sample::max_chunk_frames(input_cpal_config.channels),
+        );
+        let capture = sample::build_input_stream(
+            &input.inner,
+            &input_cpal_config,
+            input_format,
+            None,
+            move |data: &[f32], _: &cpal::InputCallbackInfo| {
+                push_frames(&queue, capture_bridge.process(data), input_channels);
+            },
+            status.error_handler(),
+        )
+        .map_err(cpal_error("Failed to build duplex input stream"))?;
+
+        let output_format = self.output_sample_format(config);
+        let output_cpal_config = self.negotiate(config, output_format, false);
+        let mut bridge = (config.sample_rate != output_cpal_config.sample_rate).then(|| {
+            PullResampler::new(
+                config.channels,
+                config.sample_rate,
+                output_cpal_config.sample_rate,
+                quality,
+                sample::max_chunk_frames(output_cpal_config.channels),
+            )
+        });
+        // The processor runs on device chunks, or on the resampler's slightly larger blocks.
+        let chunk_frames = sample::max_chunk_frames(output_cpal_config.channels);
+        let ratio = config.sample_rate as f64 / output_cpal_config.sample_rate as f64;
+        let max_frames = chunk_frames.max((chunk_frames as f64 * ratio).ceil() as usize + 1);
+        let mut captured = vec![0.0; max_frames * input_channels];
+
+        let stream = sample::build_output_stream(
+            &self.inner,
+            &output_cpal_config,
+            output_format,
+            Some(status.clone()),
+            move |data: &mut [f32], info: &cpal::OutputCallbackInfo| {
+                let mut run = |out: &mut [f32]| {
+                    let captured = &mut captured[..out.len() / output_channels * input_channels];
+                    compensator.read(captured);
+                    processor.process(captured, out, info);
+                };
+                match &mut bridge {
+                    None => run(data),
+                    Some(bridge) => bridge.fill(data, run),
+                }
+            },
+            status.error_handler(),
+        )
+        .map_err(cpal_error("Failed to build duplex output stream"))?;
+
+        Ok(AudioStream::new(stream, status).with_input(capture))
+    }
+}
+
+/// Queues whole frames only, so the reader never sees a partial one.
+fn push_frames(queue: &RingBuffer, data: &[f32], channels: usize) {
+    let free = queue.capacity() - queue.len();
+    let count = data.len().min(free - free % channels);
+    queue.push_slice(&data[..count]);
+}
+
+/// Reads input captured on one clock at the pace another clock asks for it.
+///
+/// The queue is held near `target` frames: the read rate rises a little while more is
+/// queued and falls while less is, so it settles on the ratio between the two clocks
+/// without dropping or repeating frames. Positions between frames are interpolated
+/// linearly.
+pub(crate) struct DriftCompensator {
+    queue: Arc<RingBuffer>,
+    channels: usize,
+    target: usize,
+    /// Input frames consumed per output frame.
+    ratio: f64,
+    fraction: f64,
+    current: Vec<f32>,
+    next: Vec<f32>,
+    primed: bool,
+}
+
+impl DriftCompensator {
+    pub(crate) fn new(channels: u16, target: usize) -> Self {
+        let channels = channels.max(1) as usize;
+        let target = target.max(2);
+        DriftCompensator {
+            queue: Arc::new(RingBuffer::new(target * RESYNC_FACTOR * 2 * channels)),
+            channels,
+            target,
+            ratio: 1.0,
+            fraction: 0.0,
+            current: vec![0.0; channels],
+            next: vec![0.0; channels],
+            primed: false,
+        }
+    }
+
+    pub(crate) fn queue(&self) -> Arc<RingBuffer> {
+        self.queue.clone()
+    }
+
+    fn queued(&self) -> usize {
+        self.queue.len() / self.channels
+    }
+
+    /// Fills `out` with interleaved input frames, or silence until enough is queued.
+    pub(crate) fn read(&mut self, out: &mut [f32]) {
+        let queued = self.queued();
+        if !self.primed {
+            if queued < self.target {
+                out.fill(0.0);
+                return;
+            }
+            // Start from the newest input so the latency is the target, not the wait.
+            self.queue.discard((queued - self.target) * self.channels);
+            self.queue.pop_slice(&mut self.current);
+            self.queue.pop_slice(&mut self.next);
+            self.fraction = 0.0;
+            self.ratio = 1.0;
+            self.primed = true;
+        } else if queued > self.target * RESYNC_FACTOR {
+            // The output stalled; skipping ahead beats minutes of sped-up audio.
+            self.queue.discard((queued - self.target) * self.channels);
+        }
+
+        let error = (self.queued() as f64 - self.target as f64) / self.target as f64;
+        let wanted = 1.0 + (error * DRIFT_GAIN).clamp(-MAX_CORRECTION, MAX_CORRECTION);
+        self.ratio += (wanted - self.ratio) * SMOOTHING;
+
+        for (i, frame) in out.chunks_exact_mut(self.channels).enumerate() {
+            let fraction = self.fraction as f32;
+            for ((sample, a), b) in frame.iter_mut().zip(&self.current).zip(&self.next) {
+                *sample = a + (b - a) * fraction;
+            }
+            self.fraction += self.ratio;
+            while self.fraction >= 1.0 {
+                self.fraction -= 1.0;
+                std::mem::swap(&mut self.current, &mut self.next);
+                if self.queue.pop_slice(&mut self.next) < self.channels {
+                    // Ran dry: play silence until the target has built up again.
+                    self.primed = false;
+                    out[(i + 1) * self.channels..].fill(0.0);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::null_host::{create_null_host, NullDeviceOptions, NullHostOptions};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_compensator_follows_faster_input() {
+        let mut compensator = DriftCompensator::new(1, 480);
+        let queue = compensator.queue();
+        let mut out = vec![0.0; 256];
+
+        // The input clock runs 0.2% fast; its samples count frames so the slope is the ratio.
+        let (mut next, mut carry) = (0.0f32, 0.0f64);
+        for block in 0..1000 {
+            carry += 256.0 * 1.002;
+            let frames = carry as usize;
+            carry -= frames as f64;
+            let input: Vec<f32> = (0..frames)
+                .map(|_| {
+                    next += 1.0;
+                    next
+                })
+                .collect();
+            push_frames(&queue, &input, 1);
+            compensator.read(&mut out);
+            if block == 0 {
+                assert!(out.iter().all(|s| *s == 0.0));
+            }
+        }
+
+        let slope = (out[255] - out[0]) as f64 / 255.0;
+        assert!((slope - 1.002).abs() < 0.0005, "slope {}", slope);
+        assert!(out.iter().all(|s| *s > 0.0));
+        // Before each read the queue sits a little above the target.
+        assert!((480..720).contains(&(queue.len() + out.len())));
+        assert_eq!(queue.overruns(), 0);
+    }
+
+    #[test]
+    fn test_duplex_stream_hands_input_to_processor() {
+        let host = create_null_host(Some(NullHostOptions {
+            devices: Some(vec![NullDeviceOptions {
+                frequency: Some(440.0),
+                ..Default::default()
+            }]),
+            speed: Some(50.0),
+            ..Default::default()
+        }));
+        let device = host.devices().unwrap().remove(0);
+        let config = device.default_output_config().unwrap();
+        let heard = Arc::new(AtomicBool::new(false));
+        let flag = heard.clone();
+
+        let mut stream = device
+            .build_duplex_stream(
+                &device,
+                &config,
+                DuplexStreamOptions::default(),
+                move |input: &[f32], output: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                    assert_eq!(input.len(), output.len());
+                    output.copy_from_slice(input);
+                    if input.iter().any(|s| *s != 0.0) {
+                        flag.store(true, Ordering::Relaxed);
+                    }
+                },
+            )
+            .unwrap();
+        stream.play().unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !heard.load(Ordering::Relaxed) && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        stream.close().unwrap();
+        assert!(heard.load(Ordering::Relaxed));
+    }
+}
@@ -0,0 +1,139 @@
+use crate::callback::ChunkCallback;
+use crate::device::RecordOptions;
+use crate::error::{io_error, ErrorCode};
+use crate::flac::FlacEncoder;
+use crate::recorder::{ChunkWriter, SampleSink};
+use crate::wav::{WavFormat, WavWriter};
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+/// Container and codec of recorded audio.
+#[napi]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioEncoding {
+    Wav,
+    Flac,
+    /// Opus in an Ogg container; needs the `opus` build feature.
+    Opus,
+}
+
+impl AudioEncoding {
+    fn from_path(path: &str) -> Self {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("flac") => AudioEncoding::Flac,
+            Some("opus" | "ogg" | "oga") => AudioEncoding::Opus,
+            _ => AudioEncoding::Wav,
+        }
+    }
+}
+
+/// FLAC keeps 16 bits unless a wider WAV format was asked for.
+fn flac_bits(options: &RecordOptions) -> u32 {
+    match options.format.unwrap_or_default() {
+        WavFormat::Int16 => 16,
+        _ => 24,
+    }
+}
+
+/// Opens `path` for writing in the encoding from `options`, or the one its extension names.
+pub(crate) fn file_sink(
+    path: &str,
+    channels: u16,
+    sample_rate: u32,
+    options: &RecordOptions,
+) -> Result<Box<dyn SampleSink>, ErrorCode> {
+    let encoding = options
+        .encoding
+        .unwrap_or_else(|| AudioEncoding::from_path(path));
+    let context = || format!("Failed to create {}", path);
+    let file = || {
+        File::create(path)
+            .map(BufWriter::new)
+            .map_err(io_error(context()))
+    };
+    Ok(match encoding {
+        AudioEncoding::Wav => Box::new(
+            WavWriter::create(
+                path,
+                channels,
+                sample_rate,
+                options.format.unwrap_or_default(),
+            )
+            .map_err(io_error(context()))?,
+        ),
+        AudioEncoding::Flac => Box::new(
+            FlacEncoder::new(file()?, channels, sample_rate, flac_bits(options))
+                .map_err(io_error(context()))?,
+        ),
+        AudioEncoding::Opus => opus_sink(file, channels, sample_rate, options)?,
+    })
+}
+
+/// Encodes into chunks handed to `callback`; FLAC unless `options` asks for Opus.
+pub(crate) fn chunk_sink(
+    callback: ChunkCallback,
+    channels: u16,
+    sample_rate: u32,
+    options: &RecordOptions,
+) -> Result<Box<dyn SampleSink>, ErrorCode> {
+    let out = ChunkWriter::new(callback);
+    let context = || "Failed to start encoder".to_string();
+    Ok(match options.encoding.unwrap_or(AudioEncoding::Flac) {
+        AudioEncoding::Wav => {
+            return Err(Error::new(
+                ErrorCode::InvalidArgument,
+                "WAV needs a seekable file; use recordToFile",
+            ))
+        }
+        AudioEncoding::Flac => Box::new(
+            FlacEncoder::new(out, channels, sample_rate, flac_bits(options))
+                .map_err(io_error(context()))?,
+        ),
+        AudioEncoding::Opus => opus_sink(|| Ok(out), channels, sample_rate, options)?,
+    })
+}
+
+#[cfg(feature = "opus")]
+fn opus_sink<W: std::io::Write + Send + 'static>(
+    open: impl FnOnce() -> Result<W, ErrorCode>,
+    channels: u16,
+    sample_rate: u32,
+    options: &RecordOptions,
+) -> Result<Box<dyn SampleSink>, ErrorCode> {
+    let encoder = crate::opus::OpusEncoder::new(open()?, channels, sample_rate, options.bitrate)
+        .map_err(io_error("Failed to start encoder".to_string()))?;
+    Ok(Box::new(encoder))
+}
+
+#[cfg(not(feature = "opus"))]
+fn opus_sink<W>(
+    _open: impl FnOnce() -> Result<W, ErrorCode>,
+    _channels: u16,
+    _sample_rate: u32,
+    _options: &RecordOptions,
+) -> Result<Box<dyn SampleSink>, ErrorCode> {
+    Err(Error::new(
+        ErrorCode::FeatureNotEnabled,
+        "Opus encoding is not enabled in this build; rebuild with the `opus` feature",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encoding_from_extension() {
+        assert_eq!(AudioEncoding::from_path("a/b.FLAC"), AudioEncoding::Flac);
+        assert_eq!(AudioEncoding::from_path("note.opus"), AudioEncoding::Opus);
+        assert_eq!(AudioEncoding::from_path("note.wav"), AudioEncoding::Wav);
+        assert_eq!(AudioEncoding::from_path("note"), AudioEncoding::Wav);
+    }
+}
@@ -1,7 +1,8 @@
+use napi::bindgen_prelude::*;
 use napi_derive::napi;
 
 #[napi]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BuildStreamError {
     DeviceNotAvailable,
     StreamConfigNotSupported,
@@ -10,63 +11,182 @@ pub enum BuildStreamError {
     BackendSpecific,
 }
 
+impl From<&cpal::BuildStreamError> for BuildStreamError {
+    fn from(err: &cpal::BuildStreamError) -> Self {
+        match err {
+            cpal::BuildStreamError::DeviceNotAvailable => BuildStreamError::DeviceNotAvailable,
+            cpal::BuildStreamError::StreamConfigNotSupported => {
+                BuildStreamError::StreamConfigNotSupported
+            }
+            cpal::BuildStreamError::InvalidArgument => BuildStreamError::InvalidArgument,
+            cpal::BuildStreamError::StreamIdOverflow => BuildStreamError::StreamIdOverflow,
+            cpal::BuildStreamError::BackendSpecific { .. } => BuildStreamError::BackendSpecific,
+        }
+    }
+}
+
 #[napi]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DefaultStreamConfigError {
     DeviceNotAvailable,
     StreamConfigNotSupported,
     BackendSpecific,
 }
 
+impl From<&cpal::DefaultStreamConfigError> for DefaultStreamConfigError {
+    fn from(err: &cpal::DefaultStreamConfigError) -> Self {
+        match err {
+            cpal::DefaultStreamConfigError::DeviceNotAvailable => {
+                DefaultStreamConfigError::DeviceNotAvailable
+            }
+            cpal::DefaultStreamConfigError::StreamTypeNotSupported => {
+                DefaultStreamConfigError::StreamConfigNotSupported
+            }
+            cpal::DefaultStreamConfigError::BackendSpecific { .. } => {
+                DefaultStreamConfigError::BackendSpecific
+            }
+        }
+    }
+}
+
 #[napi]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DeviceIdError {
     DeviceNotAvailable,
     BackendSpecific,
+    UnsupportedPlatform,
+}
+
+impl From<&cpal::DeviceIdError> for DeviceIdError {
+    fn from(err: &cpal::DeviceIdError) -> Self {
+        match err {
+            cpal::DeviceIdError::BackendSpecific { .. } => DeviceIdError::BackendSpecific,
+            cpal::DeviceIdError::UnsupportedPlatform => DeviceIdError::UnsupportedPlatform,
+        }
+    }
 }
 
 #[napi]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DeviceNameError {
     DeviceNotAvailable,
     BackendSpecific,
 }
 
+impl From<&cpal::DeviceNameError> for DeviceNameError {
+    fn from(err: &cpal::DeviceNameError) -> Self {
+        match err {
+            cpal::DeviceNameError::BackendSpecific { .. } => DeviceNameError::BackendSpecific,
+        }
+    }
+}
+
 #[napi]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DevicesError {
     BackendSpecific,
 }
 
+impl From<&cpal::DevicesError> for DevicesError {
+    fn from(err: &cpal::DevicesError) -> Self {
+        match err {
+            cpal::DevicesError::BackendSpecific { .. } => DevicesError::BackendSpecific,
+        }
+    }
+}
+
 #[napi]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PauseStreamError {
     DeviceNotAvailable,
     BackendSpecific,
 }
 
+impl From<&cpal::PauseStreamError> for PauseStreamError {
+    fn from(err: &cpal::PauseStreamError) -> Self {
+        match err {
+            cpal::PauseStreamError::DeviceNotAvailable => PauseStreamError::DeviceNotAvailable,
+            cpal::PauseStreamError::BackendSpecific { .. } => PauseStreamError::BackendSpecific,
+        }
+    }
+}
+
 #[napi]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PlayStreamError {
     DeviceNotAvailable,
     BackendSpecific,
 }
 
+impl From<&cpal::PlayStreamError> for PlayStreamError {
+    fn from(err: &cpal::PlayStreamError) -> Self {
+        match err {
+            cpal::PlayStreamError::DeviceNotAvailable => PlayStreamError::DeviceNotAvailable,
+            cpal::PlayStreamError::BackendSpecific { .. } => PlayStreamError::BackendSpecific,
+        }
+    }
+}
+
 #[napi]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum StreamError {
     DeviceNotAvailable,
     BackendSpecific,
+    StreamInvalidated,
+    BufferUnderrun,
 }
 
-#[napi]
+impl From<&cpal::StreamError> for StreamError {
+    fn from(err: &cpal::StreamError) -> Self {
+        match err {
+            cpal::StreamError::DeviceNotAvailable => StreamError::DeviceNotAvailable,
+            cpal::StreamError::StreamInvalidated => StreamError::StreamInvalidated,
+            cpal::StreamError::BufferUnderrun => StreamError::BufferUnderrun,
+            cpal::StreamError::BackendSpecific { .. } => StreamError::BackendSpecific,
+        }
+    }
+}
+
+#[napi(object)]
 #[derive(Debug, Clone)]
+pub struct StreamErrorEvent {
+    pub kind: StreamError,
+    pub description: String,
+}
+
+impl From<&cpal::StreamError> for StreamErrorEvent {
+    fn from(err: &cpal::StreamError) -> Self {
+        StreamErrorEvent {
+            kind: err.into(),
+            description: err.to_string(),
+        }
+    }
+}
+
+#[napi]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SupportedStreamConfigsError {
     DeviceNotAvailable,
     InvalidArgument,
     BackendSpecific,
 }
 
+impl From<&cpal::SupportedStreamConfigsError> for SupportedStreamConfigsError {
+    fn from(err: &cpal::SupportedStreamConfigsError) -> Self {
+        match err {
+            cpal::SupportedStreamConfigsError::DeviceNotAvailable => {
+                SupportedStreamConfigsError::DeviceNotAvailable
+            }
+            cpal::SupportedStreamConfigsError::InvalidArgument => {
+                SupportedStreamConfigsError::InvalidArgument
+            }
+            cpal::SupportedStreamConfigsError::BackendSpecific { .. } => {
+                SupportedStreamConfigsError::BackendSpecific
+            }
+        }
+    }
+}
+
 #[napi(object)]
 pub struct BackendSpecificError {
     pub description: String,
@@ -74,3 +194,283 @@ pub struct BackendSpecificError {
 
 #[napi(object)]
 pub struct HostUnavailable;
+
+/// The `code` property of errors thrown to JavaScript.
+///
+/// Cpal failures use the variant name of the matching enum above, so callers can
+/// branch on e.g. `err.code === "DeviceNotAvailable"`; anything else keeps the napi status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    DeviceNotAvailable,
+    StreamConfigNotSupported,
+    InvalidArgument,
+    StreamIdOverflow,
+    StreamInvalidated,
+    BufferUnderrun,
+    UnsupportedPlatform,
+    HostUnavailable,
+    BackendSpecific,
+    StreamClosed,
+    /// The feature needs a build with an optional Cargo feature this build lacks.
+    FeatureNotEnabled,
+    Io,
+    /// A wait such as `AudioBuffer.readExact` gave up before it could complete.
+    Timeout,
+    Napi(Status),
+}
+
+impl AsRef<str> for ErrorCode {
+    fn as_ref(&self) -> &str {
+        match self {
+            ErrorCode::DeviceNotAvailable => "DeviceNotAvailable",
+            ErrorCode::StreamConfigNotSupported => "StreamConfigNotSupported",
+            ErrorCode::InvalidArgument => "InvalidArgument",
+            ErrorCode::StreamIdOverflow => "StreamIdOverflow",
+            ErrorCode::StreamInvalidated => "StreamInvalidated",
+            ErrorCode::BufferUnderrun => "BufferUnderrun",
+            ErrorCode::UnsupportedPlatform => "UnsupportedPlatform",
+            ErrorCode::HostUnavailable => "HostUnavailable",
+            ErrorCode::BackendSpecific => "BackendSpecific",
+            ErrorCode::StreamClosed => "StreamClosed",
+            ErrorCode::FeatureNotEnabled => "FeatureNotEnabled",
+            ErrorCode::Io => "Io",
+            ErrorCode::Timeout => "Timeout",
+            ErrorCode::Napi(status) => status.as_ref(),
+        }
+    }
+}
+
+impl From<Status> for ErrorCode {
+    fn from(status: Status) -> Self {
+        ErrorCode::Napi(status)
+    }
+}
+
+impl From<BuildStreamError> for ErrorCode {
+    fn from(err: BuildStreamError) -> Self {
+        match err {
+            BuildStreamError::DeviceNotAvailable => ErrorCode::DeviceNotAvailable,
+            BuildStreamError::StreamConfigNotSupported => ErrorCode::StreamConfigNotSupported,
+            BuildStreamError::InvalidArgument => ErrorCode::InvalidArgument,
+            BuildStreamError::StreamIdOverflow => ErrorCode::StreamIdOverflow,
+            BuildStreamError::BackendSpecific => ErrorCode::BackendSpecific,
+        }
+    }
+}
+
+impl From<DefaultStreamConfigError> for ErrorCode {
+    fn from(err: DefaultStreamConfigError) -> Self {
+        match err {
+            DefaultStreamConfigError::DeviceNotAvailable => ErrorCode::DeviceNotAvailable,
+            DefaultStreamConfigError::StreamConfigNotSupported => {
+                ErrorCode::StreamConfigNotSupported
+            }
+            DefaultStreamConfigError::BackendSpecific => ErrorCode::BackendSpecific,
+        }
+    }
+}
+
+impl From<DeviceIdError> for ErrorCode {
+    fn from(err: DeviceIdError) -> Self {
+        match err {
+            DeviceIdError::DeviceNotAvailable => ErrorCode::DeviceNotAvailable,
+            DeviceIdError::BackendSpecific => ErrorCode::BackendSpecific,
+            DeviceIdError::UnsupportedPlatform => ErrorCode::UnsupportedPlatform,
+        }
+    }
+}
+
+impl From<DeviceNameError> for ErrorCode {
+    fn from(err: DeviceNameError) -> Self {
+        match err {
+            DeviceNameError::DeviceNotAvailable => ErrorCode::DeviceNotAvailable,
+            DeviceNameError::BackendSpecific => ErrorCode::BackendSpecific,
+        }
+    }
+}
+
+impl From<DevicesError> for ErrorCode {
+    fn from(err: DevicesError) -> Self {
+        match err {
+            DevicesError::BackendSpecific => ErrorCode::BackendSpecific,
+        }
+    }
+}
+
+impl From<PauseStreamError> for ErrorCode {
+    fn from(err: PauseStreamError) -> Self {
+        match err {
+            PauseStreamError::DeviceNotAvailable => ErrorCode::DeviceNotAvailable,
+            PauseStreamError::BackendSpecific => ErrorCode::BackendSpecific,
+        }
+    }
+}
+
+impl From<PlayStreamError> for ErrorCode {
+    fn from(err: PlayStreamError) -> Self {
+        match err {
+            PlayStreamError::DeviceNotAvailable => ErrorCode::DeviceNotAvailable,
+            PlayStreamError::BackendSpecific => ErrorCode::BackendSpecific,
+        }
+    }
+}
+
+impl From<StreamError> for ErrorCode {
+    fn from(err: StreamError) -> Self {
+        match err {
+            StreamError::DeviceNotAvailable => ErrorCode::DeviceNotAvailable,
+            StreamError::BackendSpecific => ErrorCode::BackendSpecific,
+            StreamError::StreamInvalidated => ErrorCode::StreamInvalidated,
+            StreamError::BufferUnderrun => ErrorCode::BufferUnderrun,
+        }
+    }
+}
+
+impl From<SupportedStreamConfigsError> for ErrorCode {
+    fn from(err: SupportedStreamConfigsError) -> Self {
+        match err {
+            SupportedStreamConfigsError::DeviceNotAvailable => ErrorCode::DeviceNotAvailable,
+            SupportedStreamConfigsError::InvalidArgument => ErrorCode::InvalidArgument,
+            SupportedStreamConfigsError::BackendSpecific => ErrorCode::BackendSpecific,
+        }
+    }
+}
+
+/// Maps a cpal error to a JS error whose `code` is the matching variant name above.
+pub(crate) trait CpalError: std::fmt::Display {
+    fn code(&self) -> ErrorCode;
+}
+
+impl CpalError for cpal::BuildStreamError {
+    fn code(&self) -> ErrorCode {
+        BuildStreamError::from(self).into()
+    }
+}
+
+impl CpalError for cpal::DefaultStreamConfigError {
+    fn code(&self) -> ErrorCode {
+        DefaultStreamConfigError::from(self).into()
+    }
+}
+
+impl CpalError for cpal::DeviceIdError {
+    fn code(&self) -> ErrorCode {
+        DeviceIdError::from(self).into()
+    }
+}
+
+impl CpalError for cpal::DeviceNameError {
+    fn code(&self) -> ErrorCode {
+        DeviceNameError::from(self).into()
+    }
+}
+
+impl CpalError for cpal::DevicesError {
+    fn code(&self) -> ErrorCode {
+        DevicesError::from(self).into()
+    }
+}
+
+impl CpalError for cpal::PauseStreamError {
+    fn code(&self) -> ErrorCode {
+        PauseStreamError::from(self).into()
+    }
+}
+
+impl CpalError for cpal::PlayStreamError {
+    fn code(&self) -> ErrorCode {
+        PlayStreamError::from(self).into()
+    }
+}
+
+impl CpalError for cpal::SupportedStreamConfigsError {
+    fn code(&self) -> ErrorCode {
+        SupportedStreamConfigsError::from(self).into()
+    }
+}
+
+impl CpalError for cpal::HostUnavailable {
+    fn code(&self) -> ErrorCode {
+        ErrorCode::HostUnavailable
+    }
+}
+
+/// `map_err` adapter: `.map_err(cpal_error("Failed to play"))`.
+pub(crate) fn cpal_error<E: CpalError>(
+    context: &'static str,
+) -> impl FnOnce(E) -> Error<ErrorCode> {
+    move |err| Error::new(err.code(), format!("{}: {}", context, err))
+}
+
+/// `map_err` adapter for file access: `.map_err(io_error(format!("Failed to open {}", path)))`.
+pub(crate) fn io_error(context: String) -> impl FnOnce(std::io::Error) -> Error<ErrorCode> {
+    move |err| Error::new(ErrorCode::Io, format!("{}: {}", context, err))
+}
+
+/// A background task that was cancelled or panicked instead of returning.
+pub(crate) fn join_error(err: tokio::task::JoinError) -> Error<ErrorCode> {
+    let status = if err.is_cancelled() {
+        Status::Cancelled
+    } else {
+        Status::GenericFailure
+    };
+    Error::new(
+        ErrorCode::Napi(status),
+        format!("Background task failed: {}", err),
+    )
+}
+
+/// Runs `future` on the async runtime and returns a promise that settles with its
+/// result. Unlike an `async fn`, a rejection keeps the `ErrorCode` of the failure.
+pub(crate) fn spawn_coded<'env, T, F>(env: &'env Env, future: F) -> Result<PromiseRaw<'env, T>>
+where
+    T: ToNapiValue + Send + 'static,
+    F: std::future::Future<Output = std::result::Result<T, Error<ErrorCode>>> + Send + 'static,
+{
+    env.spawn_future_with_callback(async move { Ok(future.await) }, |env, result| {
+        result.map_err(|err| {
+            // Build the JS error here, where its code is still known, and reject with it.
+            let value = unsafe { napi::JsError::from(err).into_value(env.raw()) };
+            Error::from(unsafe { Unknown::from_raw_unchecked(env.raw(), value) })
+        })
+    })
+}
+
+/// Re-tags a plain napi error (e.g. a throwing JS callback) so it can be returned
+/// alongside cpal errors; the original error is kept as `cause`.
+pub(crate) fn napi_error(err: Error) -> Error<ErrorCode> {
+    let mut wrapped = Error::new(ErrorCode::Napi(err.status), err.reason.clone());
+    wrapped.cause = Some(Box::new(err));
+    wrapped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codes_match_variant_names() {
+        let err = cpal::BuildStreamError::StreamConfigNotSupported;
+        assert_eq!(err.code().as_ref(), "StreamConfigNotSupported");
+
+        let err = cpal::DefaultStreamConfigError::StreamTypeNotSupported;
+        assert_eq!(err.code().as_ref(), "StreamConfigNotSupported");
+
+        let err = cpal::PlayStreamError::DeviceNotAvailable;
+        assert_eq!(err.code().as_ref(), "DeviceNotAvailable");
+
+        assert_eq!(ErrorCode::from(Status::InvalidArg).as_ref(), "InvalidArg");
+    }
+
+    #[test]
+    fn test_backend_description_is_kept() {
+        let err = cpal::DevicesError::BackendSpecific {
+            err: cpal::BackendSpecificError {
+                description: "snd_pcm_open failed".into(),
+            },
+        };
+        let js = cpal_error("Failed to get devices")(err);
+        assert_eq!(js.status, ErrorCode::BackendSpecific);
+        assert!(js.reason.contains("snd_pcm_open failed"));
+    }
+}
@@ -0,0 +1,374 @@
+use cpal::{Sample, I24};
+use std::io::{self, Write};
+
+/// Frames per FLAC frame; 4096 is what the reference encoder uses at 44.1/48 kHz.
+const BLOCK_SIZE: usize = 4096;
+const STREAMINFO_LEN: u32 = 34;
+const MAX_CHANNELS: u16 = 8;
+/// Largest 4-bit Rice parameter; 15 is the escape code.
+const MAX_RICE_PARAMETER: u32 = 14;
+
+/// Lossless FLAC encoder for interleaved `f32` samples.
+///
+/// Every channel is coded independently with the best fixed predictor (order 0–4) and a
+/// single Rice partition, which gets most of the way to the reference encoder's size at a
+/// fraction of the work. Frames are written as soon as a block is full.
+pub(crate) struct FlacEncoder<W: Write> {
+    out: W,
+    channels: usize,
+    sample_rate: u32,
+    bits: u32,
+    pending: Vec<i32>,
+    channel: Vec<i32>,
+    frame_number: u64,
+    total_frames: u64,
+    min_frame_bytes: u32,
+    max_frame_bytes: u32,
+}
+
+impl<W: Write> FlacEncoder<W> {
+    /// Writes the stream header; `bits` is 16 or 24.
+    pub(crate) fn new(out: W, channels: u16, sample_rate: u32, bits: u32) -> io::Result<Self> {
+        if channels == 0 || channels > MAX_CHANNELS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("FLAC supports 1 to {} channels", MAX_CHANNELS),
+            ));
+        }
+        let mut encoder = FlacEncoder {
+            out,
+            channels: channels as usize,
+            sample_rate,
+            bits: if bits > 16 { 24 } else { 16 },
+            pending: Vec::new(),
+            channel: Vec::new(),
+            frame_number: 0,
+            total_frames: 0,
+            min_frame_bytes: 0,
+            max_frame_bytes: 0,
+        };
+        let streaminfo = encoder.streaminfo();
+        encoder.out.write_all(b"fLaC")?;
+        encoder.out.write_all(&streaminfo)?;
+        Ok(encoder)
+    }
+
+    pub(crate) fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        let bits = self.bits;
+        self.pending
+            .extend(samples.iter().map(|s| quantize(*s, bits)));
+        let block = BLOCK_SIZE * self.channels;
+        let mut start = 0;
+        while self.pending.len() - start >= block {
+            self.encode_frame(start, BLOCK_SIZE)?;
+            start += block;
+        }
+        if start > 0 {
+            self.pending.drain(..start);
+            self.out.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Encodes what is left as a final, shorter frame and flushes the output.
+    pub(crate) fn finish(&mut self) -> io::Result<()> {
+        let frames = self.pending.len() / self.channels;
+        if frames > 0 {
+            self.encode_frame(0, frames)?;
+            self.pending.clear();
+        }
+        self.out.flush()
+    }
+
+    /// The STREAMINFO block with the totals seen so far, for patching after `finish`.
+    pub(crate) fn streaminfo(&self) -> Vec<u8> {
+        let mut w = BitWriter::default();
+        w.put(1, 1); // last metadata block
+        w.put(7, 0); // STREAMINFO
+        w.put(24, STREAMINFO_LEN as u64);
+        w.put(16, BLOCK_SIZE as u64);
+        w.put(16, BLOCK_SIZE as u64);
+        w.put(24, self.min_frame_bytes as u64);
+        w.put(24, self.max_frame_bytes as u64);
+        w.put(20, self.sample_rate as u64);
+        w.put(3, self.channels as u64 - 1);
+        w.put(5, self.bits as u64 - 1);
+        w.put(32, self.total_frames >> 4);
+        w.put(4, self.total_frames & 0xF);
+        // No MD5 signature; zero means "not computed".
+        for _ in 0..4 {
+            w.put(32, 0);
+        }
+        w.bytes
+    }
+
+    pub(crate) fn get_mut(&mut self) -> &mut W {
+        &mut self.out
+    }
+
+    fn encode_frame(&mut self, start: usize, frames: usize) -> io::Result<()> {
+        let mut w = BitWriter::default();
+        w.put(14, 0b11_1111_1111_1110); // sync code
+        w.put(1, 0);
+        w.put(1, 0); // fixed block size
+        w.put(4, 0b0111); // block size - 1 follows as 16 bits
+        w.put(4, 0); // sample rate from STREAMINFO
+        w.put(4, self.channels as u64 - 1); // independent channels
+        w.put(3, if self.bits == 24 { 0b110 } else { 0b100 });
+        w.put(1, 0);
+        w.put_utf8(self.frame_number);
+        w.put(16, frames as u64 - 1);
+        let crc = crc8(&w.bytes);
+        w.put(8, crc as u64);
+
+        for c in 0..self.channels {
+            self.channel.clear();
+            self.channel.extend(
+                self.pending[start..start + frames * self.channels]
+                    .iter()
+                    .skip(c)
+                    .step_by(self.channels),
+            );
+            encode_subframe(&mut w, &self.channel, self.bits);
+        }
+        w.align();
+        let crc = crc16(&w.bytes);
+        w.put(16, crc as u64);
+
+        let len = w.bytes.len() as u32;
+        self.min_frame_bytes = match self.frame_number {
+            0 => len,
+            _ => self.min_frame_bytes.min(len),
+        };
+        self.max_frame_bytes = self.max_frame_bytes.max(len);
+        self.frame_number += 1;
+        self.total_frames += frames as u64;
+        self.out.write_all(&w.bytes)
+    }
+}
+
+fn quantize(sample: f32, bits: u32) -> i32 {
+    let sample = sample.clamp(-1.0, 1.0);
+    if bits == 24 {
+        I24::from_sample::<f32>(sample).inner()
+    } else {
+        i16::from_sample::<f32>(sample) as i32
+    }
+}
+
+fn encode_subframe(w: &mut BitWriter, samples: &[i32], bits: u32) {
+    if samples.iter().all(|s| *s == samples[0]) {
+        w.put(8, 0); // CONSTANT
+        w.put_signed(bits, samples[0] as i64);
+        return;
+    }
+
+    let max_order = 4.min(samples.len() - 1);
+    let (order, residual) = (0..=max_order)
+        .map(|order| (order, fixed_residual(samples, order)))
+        .min_by_key(|(_, residual)| residual.iter().map(|r| r.unsigned_abs()).sum::<u64>())
+        .unwrap();
+    let folded: Vec<u64> = residual
+        .iter()
+        .map(|r| {
+            if *r >= 0 {
+                (*r as u64) << 1
+            } else {
+                ((-*r as u64) << 1) - 1
+            }
+        })
+        .collect();
+    let (parameter, rice_bits) = (0..=MAX_RICE_PARAMETER)
+        .map(|k| {
+            let bits: u64 = folded.iter().map(|u| (u >> k) + 1 + k as u64).sum();
+            (k, bits)
+        })
+        .min_by_key(|(_, bits)| *bits)
+        .unwrap();
+
+    let fixed_bits = order as u64 * bits as u64 + 10 + rice_bits;
+    if fixed_bits >= samples.len() as u64 * bits as u64 {
+        w.put(8, 0b0000_0010); // VERBATIM
+        for sample in samples {
+            w.put_signed(bits, *sample as i64);
+        }
+        return;
+    }
+
+    w.put(8, (0b001000 | order as u64) << 1); // FIXED
+    for sample in &samples[..order] {
+        w.put_signed(bits, *sample as i64);
+    }
+    w.put(2, 0); // Rice coding, 4-bit parameters
+    w.put(4, 0); // one partition
+    w.put(4, parameter as u64);
+    for u in folded {
+        w.put_unary(u >> parameter);
+        w.put(parameter, u);
+    }
+}
+
+fn fixed_residual(samples: &[i32], order: usize) -> Vec<i64> {
+    let s = |i: usize| samples[i] as i64;
+    (order..samples.len())
+        .map(|i| match order {
+            0 => s(i),
+            1 => s(i) - s(i - 1),
+            2 => s(i) - 2 * s(i - 1) + s(i - 2),
+            3 => s(i) - 3 * s(i - 1) + 3 * s(i - 2) - s(i - 3),
+            _ => s(i) - 4 * s(i - 1) + 6 * s(i - 2) - 4 * s(i - 3) + s(i - 4),
+        })
+        .collect()
+}
+
+/// MSB-first bit packer.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    /// Appends the low `count` (at most 32) bits of `value`.
+    fn put(&mut self, count: u32, value: u64) {
+        if count == 0 {
+            return;
+        }
+        self.acc = (self.acc << count) | (value & ((1 << count) - 1));
+        self.bits += count;
+        while self.bits >= 8 {
+            self.bits -= 8;
+            self.bytes.push((self.acc >> self.bits) as u8);
+        }
+    }
+
+    fn put_signed(&mut self, count: u32, value: i64) {
+        self.put(count, value as u64);
+    }
+
+    fn put_unary(&mut self, mut zeros: u64) {
+        while zeros >= 32 {
+            self.put(32, 0);
+            zeros -= 32;
+        }
+        self.put(zeros as u32 + 1, 1);
+    }
+
+    /// The UTF-8-like variable length coding FLAC uses for frame numbers.
+    fn put_utf8(&mut self, value: u64) {
+        if value < 0x80 {
+            return self.put(8, value);
+        }
+        let continuation = match value {
+            v if v < 0x800 => 1,
+            v if v < 0x1_0000 => 2,
+            v if v < 0x20_0000 => 3,
+            v if v < 0x400_0000 => 4,
+            _ => 5,
+        };
+        let lead = (0xFF00u64 >> (continuation + 1)) & 0xFF;
+        self.put(8, lead | (value >> (6 * continuation)));
+        for i in (0..continuation).rev() {
+            self.put(8, 0x80 | ((value >> (6 * i)) & 0x3F));
+        }
+    }
+
+    fn align(&mut self) {
+        if self.bits > 0 {
+            self.put(8 - self.bits, 0);
+        }
+    }
+}
+
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |mut crc, byte| {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0u16, |mut crc, byte| {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::FileDecoder;
+    use std::io::{Seek, SeekFrom};
+
+    #[test]
+    fn test_round_trips_through_decoder() {
+        let path = std::env::temp_dir().join("cpal-napi-flac.flac");
+        let frames = BLOCK_SIZE * 2 + 100;
+        let samples: Vec<f32> = (0..frames)
+            .flat_map(|i| {
+                let t = i as f32 / 48000.0;
+                [
+                    (t * 440.0 * std::f32::consts::TAU).sin() * 0.5,
+                    if i % 7 == 0 { 0.25 } else { -0.25 },
+                ]
+            })
+            .collect();
+
+        let file = std::fs::File::create(&path).unwrap();
+        let mut encoder = FlacEncoder::new(file, 2, 48000, 16).unwrap();
+        encoder.write(&samples[..1000]).unwrap();
+        encoder.write(&samples[1000..]).unwrap();
+        encoder.finish().unwrap();
+        let info = encoder.streaminfo();
+        let file = encoder.get_mut();
+        file.seek(SeekFrom::Start(4)).unwrap();
+        file.write_all(&info).unwrap();
+
+        let mut decoder = FileDecoder::open(&path).unwrap();
+        assert_eq!(decoder.info().frames, Some(frames as i64));
+        let mut decoded = Vec::new();
+        while decoder.next_block(&mut decoded).unwrap() {}
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(decoded.len(), samples.len());
+        for (a, b) in decoded.iter().zip(&samples) {
+            assert!((a - b).abs() < 1.0 / 16384.0);
+        }
+    }
+
+    #[test]
+    fn test_constant_and_24_bit() {
+        let mut encoder = FlacEncoder::new(Vec::new(), 1, 44100, 24).unwrap();
+        encoder.write(&[0.5; BLOCK_SIZE]).unwrap();
+        let bytes = encoder.get_mut().clone();
+        // Header, STREAMINFO, then a frame holding one CONSTANT subframe.
+        assert_eq!(&bytes[..4], b"fLaC");
+        let frame = &bytes[4 + 4 + STREAMINFO_LEN as usize..];
+        assert_eq!(&frame[..2], &[0xFF, 0xF8]);
+        assert!(frame.len() < 16);
+    }
+
+    #[test]
+    fn test_utf8_frame_numbers() {
+        let mut w = BitWriter::default();
+        w.put_utf8(0x7F);
+        w.put_utf8(0x80);
+        w.put_utf8(0x1234);
+        assert_eq!(w.bytes, vec![0x7F, 0xC2, 0x80, 0xE1, 0x88, 0xB4]);
+    }
+}
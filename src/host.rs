@@ -1,53 +1,285 @@
-use crate::device::AudioDevice;
-use cpal::traits::HostTrait;
+use crate::device::{AudioDevice, DeviceId};
+use crate::device_description::{DeviceDescription, DeviceFilter};
+use crate::error::{cpal_error, napi_error, ErrorCode};
+use crate::null_host::{NullDeviceOptions, NullDevices};
+use crate::types::{DevicesFiltered, InputDevices, OutputDevices};
+use crate::watch::{DeviceChangeEvent, DeviceWatcher, WatchDevicesOptions};
+use cpal::traits::{DeviceTrait, HostTrait};
 use napi::bindgen_prelude::*;
 use napi_derive::napi;
+use std::sync::Arc;
 
 #[napi]
 pub struct AudioHost {
-    pub(crate) inner: cpal::Host,
+    pub(crate) inner: Arc<cpal::Host>,
+    /// The device list of a null host, which can change while it runs.
+    pub(crate) null_devices: Option<Arc<NullDevices>>,
+}
+
+impl AudioHost {
+    pub(crate) fn new(host: cpal::Host) -> Self {
+        AudioHost {
+            inner: Arc::new(host),
+            null_devices: None,
+        }
+    }
+
+    /// Picks a device by the key `key` gives it; see `find_match`.
+    fn find_device(
+        &self,
+        wanted: &str,
+        substring: bool,
+        key: impl Fn(&cpal::Device) -> Option<String>,
+    ) -> Result<AudioDevice, ErrorCode> {
+        // Devices whose key cannot be read are left out rather than matched as "".
+        let (devices, keys): (Vec<_>, Vec<_>) = self
+            .inner
+            .devices()
+            .map_err(cpal_error("Failed to get devices"))?
+            .filter_map(|d| key(&d).map(|k| (d, k)))
+            .unzip();
+        let index = find_match(&keys, wanted, substring).ok_or_else(|| {
+            Error::new(
+                ErrorCode::DeviceNotAvailable,
+                format!("No device matches {:?}", wanted),
+            )
+        })?;
+        Ok(AudioDevice {
+            inner: devices.into_iter().nth(index).unwrap(),
+            realtime: true,
+        })
+    }
+
+    fn null_devices(&self) -> Result<&NullDevices, ErrorCode> {
+        self.null_devices.as_deref().ok_or_else(|| {
+            Error::new(
+                ErrorCode::InvalidArgument,
+                "Only null hosts can connect and disconnect devices",
+            )
+        })
+    }
 }
 
 #[napi]
 impl AudioHost {
     #[napi]
     pub fn name(&self) -> String {
-        self.inner.id().name().to_string()
+        match self.inner.id() {
+            cpal::HostId::Custom => "Null".to_string(),
+            id => id.name().to_string(),
+        }
     }
 
     #[napi]
-    pub fn devices(&self) -> Result<Vec<AudioDevice>> {
+    pub fn devices(&self) -> Result<Vec<AudioDevice>, ErrorCode> {
         let devices = self
             .inner
             .devices()
-            .map_err(|e| Error::from_reason(format!("Failed to get devices: {}", e)))?;
-        Ok(devices.map(|d| AudioDevice { inner: d }).collect())
+            .map_err(cpal_error("Failed to get devices"))?;
+        Ok(devices
+            .map(|d| AudioDevice {
+                inner: d,
+                realtime: true,
+            })
+            .collect())
+    }
+
+    /// Devices that can capture audio, duplex ones included.
+    #[napi]
+    pub fn input_devices(&self) -> Result<InputDevices, ErrorCode> {
+        let devices = self
+            .inner
+            .input_devices()
+            .map_err(cpal_error("Failed to get input devices"))?;
+        Ok(devices
+            .map(|d| AudioDevice {
+                inner: d,
+                realtime: true,
+            })
+            .collect())
+    }
+
+    /// Devices that can play audio, duplex ones included.
+    #[napi]
+    pub fn output_devices(&self) -> Result<OutputDevices, ErrorCode> {
+        let devices = self
+            .inner
+            .output_devices()
+            .map_err(cpal_error("Failed to get output devices"))?;
+        Ok(devices
+            .map(|d| AudioDevice {
+                inner: d,
+                realtime: true,
+            })
+            .collect())
+    }
+
+    /// Devices matching `filter`, or for which `filter` returns true when it is a function
+    /// of the device's description.
+    #[napi(ts_args_type = "filter: DeviceFilter | ((description: DeviceDescription) => boolean)")]
+    pub fn devices_filtered(
+        &self,
+        filter: Either<Function<'_, DeviceDescription, bool>, DeviceFilter>,
+    ) -> Result<DevicesFiltered, ErrorCode> {
+        let mut matched = Vec::new();
+        for device in self.devices()? {
+            let keep = match &filter {
+                Either::A(predicate) => {
+                    predicate.call(device.description()?).map_err(napi_error)?
+                }
+                Either::B(filter) => device.matches(filter)?,
+            };
+            if keep {
+                matched.push(device);
+            }
+        }
+        Ok(matched)
     }
 
     #[napi]
     pub fn default_input_device(&self) -> Option<AudioDevice> {
-        self.inner
-            .default_input_device()
-            .map(|d| AudioDevice { inner: d })
+        self.inner.default_input_device().map(|d| AudioDevice {
+            inner: d,
+            realtime: true,
+        })
     }
 
     #[napi]
     pub fn default_output_device(&self) -> Option<AudioDevice> {
-        self.inner
-            .default_output_device()
-            .map(|d| AudioDevice { inner: d })
+        self.inner.default_output_device().map(|d| AudioDevice {
+            inner: d,
+            realtime: true,
+        })
+    }
+
+    /// Finds a device by the id `AudioDevice.id()` returned, e.g. in an earlier run.
+    ///
+    /// Falls back to the only device whose id differs just in numbers, since ALSA card
+    /// indices change when devices are plugged in a different order.
+    #[napi(ts_args_type = "id: DeviceId | string")]
+    pub fn device_by_id(&self, id: Either<DeviceId, String>) -> Result<AudioDevice, ErrorCode> {
+        let id = match id {
+            Either::A(id) => id.id,
+            Either::B(id) => id,
+        };
+        self.find_device(&id, false, |d| d.id().ok().map(|id| id.to_string()))
+    }
+
+    /// Finds a device by name: an exact (case-insensitive) match, then the only one that
+    /// differs just in numbers, then the first whose name contains `pattern`.
+    #[napi]
+    pub fn device_by_name(&self, pattern: String) -> Result<AudioDevice, ErrorCode> {
+        self.find_device(&pattern, true, |d| {
+            d.description().ok().map(|d| d.name().to_string())
+        })
+    }
+
+    /// Polls the host's devices on a background thread and reports devices that appear or
+    /// disappear and changes of the default input or output device.
+    #[napi(
+        ts_args_type = "callback: (event: DeviceChangeEvent) => void, options?: WatchDevicesOptions"
+    )]
+    pub fn watch_devices(
+        &self,
+        callback: Function<'_, DeviceChangeEvent, ()>,
+        options: Option<WatchDevicesOptions>,
+    ) -> Result<DeviceWatcher, ErrorCode> {
+        let callback = callback
+            .build_threadsafe_function()
+            .callee_handled::<false>()
+            .weak::<true>()
+            .build()
+            .map_err(napi_error)?;
+        Ok(DeviceWatcher::spawn(
+            self.inner.clone(),
+            callback,
+            options.unwrap_or_default(),
+        ))
+    }
+
+    /// Adds a virtual device to a null host, as if it had been plugged in.
+    #[napi]
+    pub fn connect_null_device(&self, options: NullDeviceOptions) -> Result<(), ErrorCode> {
+        self.null_devices()?.connect(options);
+        Ok(())
+    }
+
+    /// Removes the null host's devices called `name`; returns whether there were any.
+    #[napi]
+    pub fn disconnect_null_device(&self, name: String) -> Result<bool, ErrorCode> {
+        Ok(self.null_devices()?.disconnect(&name))
     }
 }
 
+/// Index of the candidate best matching `wanted`: equal, equal ignoring case, the only one
+/// equal once digits and punctuation are dropped, or with `substring` the first containing it.
+fn find_match(candidates: &[String], wanted: &str, substring: bool) -> Option<usize> {
+    if let Some(index) = candidates.iter().position(|c| c == wanted) {
+        return Some(index);
+    }
+    if let Some(index) = candidates
+        .iter()
+        .position(|c| c.eq_ignore_ascii_case(wanted))
+    {
+        return Some(index);
+    }
+    let key = fuzzy_key(wanted);
+    let mut fuzzy = candidates
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| !key.is_empty() && fuzzy_key(c) == key)
+        .map(|(i, _)| i);
+    if let (Some(index), None) = (fuzzy.next(), fuzzy.next()) {
+        return Some(index);
+    }
+    if !substring || wanted.is_empty() {
+        return None;
+    }
+    let wanted = wanted.to_lowercase();
+    candidates
+        .iter()
+        .position(|c| c.to_lowercase().contains(&wanted))
+}
+
+/// `name` in lower case with its index numbers blurred, so `USB Mic 2` matches `USB Mic 3`
+/// and `hw:1,0` matches `hw:2,0`. Only trailing indices count: ` N` and `#N` become ` *` and
+/// `#*`, the numeric card in `hw:N,M` becomes `*`, and ALSA's `_N` suffix for repeated card
+/// names (`CARD=USB_1`) is dropped, as the first such card has none. Other numbers, like
+/// the `DEV=3` of an HDMI output, still have to match.
+fn fuzzy_key(name: &str) -> String {
+    let chars: Vec<char> = name.to_lowercase().chars().collect();
+    let mut key = String::with_capacity(chars.len());
+    let mut i = 0;
+    while i < chars.len() {
+        if !chars[i].is_ascii_digit() {
+            key.push(chars[i]);
+            i += 1;
+            continue;
+        }
+        let end = i + chars[i..].iter().take_while(|c| c.is_ascii_digit()).count();
+        let trailing = matches!(chars.get(end), None | Some(','));
+        match i.checked_sub(1).map(|j| chars[j]) {
+            Some('_') if trailing => {
+                key.pop();
+            }
+            Some(' ' | '#' | ':') if trailing => key.push('*'),
+            _ => key.extend(&chars[i..end]),
+        }
+        i = end;
+    }
+    key
+}
+
 #[napi]
 pub fn get_default_host() -> AudioHost {
-    AudioHost {
-        inner: cpal::default_host(),
-    }
+    AudioHost::new(cpal::default_host())
 }
 
 #[napi]
-pub fn host_from_id(id: crate::types::HostId) -> Result<AudioHost> {
+pub fn host_from_id(id: crate::types::HostId) -> Result<AudioHost, ErrorCode> {
+    if id == crate::types::HostId::Null {
+        return Ok(crate::null_host::create_null_host(None));
+    }
     let cpal_id = match id {
         #[cfg(target_os = "linux")]
         crate::types::HostId::Alsa => Some(cpal::HostId::Alsa),
@@ -63,8 +295,8 @@ pub fn host_from_id(id: crate::types::HostId) -> Result<AudioHost> {
 
     if let Some(cid) = cpal_id {
         cpal::host_from_id(cid)
-            .map(|h| AudioHost { inner: h })
-            .map_err(|e| Error::from_reason(format!("Failed to initialize host: {}", e)))
+            .map(AudioHost::new)
+            .map_err(cpal_error("Failed to initialize host"))
     } else {
         // Fallback for cases where we can't name the ID directly but it might be available
         let name = match id {
@@ -81,8 +313,13 @@ pub fn host_from_id(id: crate::types::HostId) -> Result<AudioHost> {
             .iter()
             .find(|h| h.name().to_lowercase() == name)
             .and_then(|h| cpal::host_from_id(*h).ok())
-            .map(|h| AudioHost { inner: h })
-            .ok_or_else(|| Error::from_reason(format!("Host not available: {:?}", id)))
+            .map(AudioHost::new)
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorCode::HostUnavailable,
+                    format!("Host not available: {:?}", id),
+                )
+            })
     }
 }
 
@@ -103,11 +340,13 @@ pub fn get_all_hosts_list() -> Vec<crate::types::HostId> {
         crate::types::HostId::Asio,
         crate::types::HostId::CoreAudio,
         crate::types::HostId::Emscripten,
+        crate::types::HostId::Null,
     ]
 }
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::device_description::DeviceDirection;
 
     #[test]
     fn test_available_hosts() {
@@ -115,6 +354,147 @@ mod tests {
         assert!(!hosts.is_empty());
     }
 
+    #[test]
+    fn test_find_match() {
+        let names = [
+            "hw:CARD=PCH,DEV=0".to_string(),
+            "hw:CARD=PCH,DEV=3".to_string(),
+            "USB Headset #2".to_string(),
+        ];
+        assert_eq!(find_match(&names, "hw:CARD=PCH,DEV=3", false), Some(1));
+        assert_eq!(find_match(&names, "usb headset #2", false), Some(2));
+        // Index changed: unique fuzzy match.
+        assert_eq!(find_match(&names, "USB Headset #1", false), Some(2));
+        assert_eq!(find_match(&names, "USB Headset", false), None);
+        // Device numbers are not indices.
+        assert_eq!(find_match(&names, "hw:CARD=PCH,DEV=7", false), None);
+        assert_eq!(find_match(&names[..1], "hw:CARD=PCH,DEV=3", false), None);
+        assert_eq!(
+            find_match(&["hw:2,0".to_string()], "hw:1,0", false),
+            Some(0)
+        );
+        assert_eq!(find_match(&["hw:2,3".to_string()], "hw:1,0", false), None);
+        assert_eq!(
+            find_match(
+                &["hw:CARD=USB,DEV=0".to_string()],
+                "hw:CARD=USB_1,DEV=0",
+                false
+            ),
+            Some(0)
+        );
+        assert_eq!(find_match(&names, "headset", false), None);
+        assert_eq!(find_match(&names, "headset", true), Some(2));
+        assert_eq!(find_match(&names, "", true), None);
+    }
+
+    #[test]
+    fn test_device_lookup_on_null_host() {
+        let host = crate::null_host::create_null_host(Some(crate::null_host::NullHostOptions {
+            devices: Some(vec![
+                crate::null_host::NullDeviceOptions {
+                    name: Some("Speakers".to_string()),
+                    ..Default::default()
+                },
+                crate::null_host::NullDeviceOptions {
+                    name: Some("USB Mic 2".to_string()),
+                    ..Default::default()
+                },
+            ]),
+            ..Default::default()
+        }));
+        let mic = host.device_by_name("mic".to_string()).unwrap();
+        assert_eq!(mic.name().unwrap(), "USB Mic 2");
+        let id = mic.id().unwrap();
+        let again = host.device_by_id(Either::A(id.clone())).unwrap();
+        assert_eq!(again.id().unwrap(), id);
+        let renumbered = id.id.replace('2', "3");
+        assert_eq!(
+            host.device_by_id(Either::B(renumbered))
+                .unwrap()
+                .id()
+                .unwrap(),
+            id
+        );
+        assert!(host.device_by_name("Headphones".to_string()).is_err());
+        // A device whose key cannot be read never matches, not even an empty query.
+        assert!(host.find_device("", false, |_| None).is_err());
+    }
+
+    #[test]
+    fn test_devices_by_direction_and_filter() {
+        let device = |name: &str, input: u16, output: u16, rates: Vec<u32>| {
+            crate::null_host::NullDeviceOptions {
+                name: Some(name.to_string()),
+                input_channels: Some(input),
+                output_channels: Some(output),
+                sample_rates: Some(rates),
+                ..Default::default()
+            }
+        };
+        let host = crate::null_host::create_null_host(Some(crate::null_host::NullHostOptions {
+            devices: Some(vec![
+                device("Mic", 1, 0, vec![16000]),
+                device("Speakers", 0, 2, vec![44100, 48000]),
+                device("Interface", 8, 8, vec![48000, 96000]),
+                device("Headset", 1, 2, vec![48000]),
+            ]),
+            ..Default::default()
+        }));
+        let names = |devices: Vec<AudioDevice>| -> Vec<String> {
+            devices.iter().map(|d| d.name().unwrap()).collect()
+        };
+        assert_eq!(
+            names(host.input_devices().unwrap()),
+            ["Mic", "Interface", "Headset"]
+        );
+        assert_eq!(
+            names(host.output_devices().unwrap()),
+            ["Speakers", "Interface", "Headset"]
+        );
+
+        let filtered =
+            |filter: DeviceFilter| names(host.devices_filtered(Either::B(filter)).unwrap());
+        assert_eq!(
+            filtered(DeviceFilter {
+                direction: Some(DeviceDirection::Output),
+                sample_rate: Some(48000),
+                ..Default::default()
+            }),
+            ["Speakers", "Interface", "Headset"]
+        );
+        assert_eq!(
+            filtered(DeviceFilter {
+                direction: Some(DeviceDirection::Input),
+                min_channels: Some(2),
+                ..Default::default()
+            }),
+            ["Interface"]
+        );
+        assert_eq!(
+            filtered(DeviceFilter {
+                direction: Some(DeviceDirection::Duplex),
+                ..Default::default()
+            }),
+            ["Interface", "Headset"]
+        );
+        // The headset has two output channels but only one input channel.
+        assert_eq!(
+            filtered(DeviceFilter {
+                direction: Some(DeviceDirection::Duplex),
+                min_channels: Some(2),
+                ..Default::default()
+            }),
+            ["Interface"]
+        );
+        assert_eq!(
+            filtered(DeviceFilter {
+                sample_rate: Some(16000),
+                ..Default::default()
+            }),
+            ["Mic"]
+        );
+    }
+
     #[test]
     fn test_host_from_id() {
         let hosts = get_all_hosts_list();
@@ -1,20 +1,47 @@
 pub mod buffer;
+pub mod callback;
+pub mod channels;
 pub mod config;
+mod decoder;
 pub mod device;
 pub mod device_description;
+pub mod duplex;
+pub mod encoding;
 pub mod error;
+mod flac;
 pub mod host;
+pub mod null_host;
+pub mod offline;
+#[cfg(feature = "opus")]
+mod opus;
+pub mod player;
+mod recorder;
+pub mod resampler;
+mod ring;
+mod sample;
 pub mod stream;
 pub mod types;
+pub mod watch;
+pub mod wav;
 
 pub use buffer::*;
+pub use callback::*;
+pub use channels::*;
 pub use config::*;
 pub use device::*;
 pub use device_description::*;
+pub use duplex::*;
+pub use encoding::*;
 pub use error::*;
 pub use host::*;
+pub use null_host::*;
+pub use offline::*;
+pub use player::*;
+pub use resampler::*;
 pub use stream::*;
 pub use types::*;
+pub use watch::*;
+pub use wav::*;
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,856 @@
+use crate::host::AudioHost;
+use crate::offline::{OfflineEngine, Voice};
+use crate::ring::RingBuffer;
+use crate::sample;
+use crate::types::SampleFormat;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use napi_derive::napi;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+const DEFAULT_DEVICE_NAME: &str = "Null Device";
+const DEFAULT_CHANNELS: u16 = 2;
+const DEFAULT_SAMPLE_RATE: u32 = 48000;
+const DEFAULT_BLOCK_FRAMES: u32 = 480;
+
+#[napi(object)]
+#[derive(Debug, Clone, Default)]
+pub struct NullDeviceOptions {
+    pub name: Option<String>,
+    /// Input channels (default 2); 0 makes the device output-only.
+    pub input_channels: Option<u16>,
+    /// Output channels (default 2); 0 makes the device input-only.
+    pub output_channels: Option<u16>,
+    /// Supported rates, the first being the default (48000 by default).
+    pub sample_rates: Option<Vec<u32>>,
+    /// Supported formats, the first being the default (`F32` by default).
+    pub sample_formats: Option<Vec<SampleFormat>>,
+    /// Frames per callback unless the config asks for a fixed buffer size (default 480).
+    pub block_frames: Option<u32>,
+    /// Frequency of the sine wave inputs capture; silence when unset.
+    pub frequency: Option<f64>,
+}
+
+/// A virtual output device whose rendered audio is captured by a paired input device.
+#[napi(object)]
+#[derive(Debug, Clone, Default)]
+pub struct LoopbackOptions {
+    /// Base name; the devices are called "<name> Output" and "<name> Input" ("Loopback" by default).
+    pub name: Option<String>,
+    pub channels: Option<u16>,
+    pub sample_rate: Option<u32>,
+    pub sample_formats: Option<Vec<SampleFormat>>,
+    pub block_frames: Option<u32>,
+    /// Frames of silence the input lags behind the output (default 0).
+    pub latency_frames: Option<u32>,
+    /// How much faster the input clock runs than the output clock, in parts per million.
+    pub drift_ppm: Option<f64>,
+}
+
+#[napi(object)]
+#[derive(Debug, Clone, Default)]
+pub struct NullHostOptions {
+    /// Devices to expose; a single stereo duplex device by default.
+    pub devices: Option<Vec<NullDeviceOptions>>,
+    /// Loopback pairs, listed after `devices`.
+    pub loopbacks: Option<Vec<LoopbackOptions>>,
+    /// How fast the simulated clock runs relative to real time (default 1).
+    pub speed: Option<f64>,
+}
+
+/// Creates a host whose devices need no audio hardware.
+///
+/// Streams run on a background thread and report timestamps from a simulated clock
+/// that only advances while they play, so runs are reproducible.
+#[napi]
+pub fn create_null_host(options: Option<NullHostOptions>) -> AudioHost {
+    let options = options.unwrap_or_default();
+    let speed = options.speed.filter(|s| *s > 0.0).unwrap_or(1.0);
+    let mut devices: Vec<NullDevice> = options
+        .devices
+        .unwrap_or_else(|| vec![NullDeviceOptions::default()])
+        .into_iter()
+        .map(|spec| NullDevice {
+            spec: Arc::new(NullDeviceSpec::new(spec)),
+            speed,
+        })
+        .collect();
+    for loopback in options.loopbacks.unwrap_or_default() {
+        devices.extend(loopback_pair(loopback, speed));
+    }
+
+    let devices = Arc::new(NullDevices {
+        devices: Mutex::new(devices),
+        speed,
+    });
+    let host = cpal::platform::CustomHost::from_host(NullHost {
+        devices: devices.clone(),
+    });
+    AudioHost {
+        null_devices: Some(devices),
+        ..AudioHost::new(cpal::Host::from(host))
+    }
+}
+
+/// The devices of a null host, shared so they can come and go while it is in use.
+pub(crate) struct NullDevices {
+    devices: Mutex<Vec<NullDevice>>,
+    speed: f64,
+}
+
+impl NullDevices {
+    pub(crate) fn connect(&self, options: NullDeviceOptions) {
+        self.devices.lock().unwrap().push(NullDevice {
+            spec: Arc::new(NullDeviceSpec::new(options)),
+            speed: self.speed,
+        });
+    }
+
+    pub(crate) fn disconnect(&self, name: &str) -> bool {
+        let mut devices = self.devices.lock().unwrap();
+        let before = devices.len();
+        devices.retain(|d| d.spec.name != name);
+        devices.len() != before
+    }
+}
+
+struct NullDeviceSpec {
+    name: String,
+    input_channels: u16,
+    output_channels: u16,
+    sample_rates: Vec<u32>,
+    sample_formats: Vec<cpal::SampleFormat>,
+    block_frames: u32,
+    frequency: Option<f64>,
+    route: Route,
+}
+
+/// Where a device's audio goes to or comes from besides its own stream.
+enum Route {
+    None,
+    /// Output audio is written to the link.
+    Sink(Arc<Link>),
+    /// Input audio is read from the link.
+    Source(Arc<Link>),
+    /// Output streams are advanced by an offline renderer instead of a thread.
+    Offline(Arc<OfflineEngine>),
+}
+
+/// Carries audio from a loopback output device to its paired input device.
+struct Link {
+    ring: RingBuffer,
+}
+
+impl Link {
+    fn new(channels: u16, sample_rate: u32, latency_frames: u32) -> Self {
+        let channels = channels as usize;
+        let latency = latency_frames as usize * channels;
+        let ring = RingBuffer::new(sample_rate as usize * channels + latency);
+        ring.push_slice(&vec![0.0; latency]);
+        Link { ring }
+    }
+}
+
+fn loopback_pair(options: LoopbackOptions, speed: f64) -> [NullDevice; 2] {
+    let name = options.name.unwrap_or_else(|| "Loopback".to_string());
+    let channels = options.channels.unwrap_or(DEFAULT_CHANNELS).max(1);
+    let sample_rate = options.sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE).max(1);
+    let link = Arc::new(Link::new(
+        channels,
+        sample_rate,
+        options.latency_frames.unwrap_or(0),
+    ));
+    let spec = |suffix: &str, input: u16, output: u16, route: Route| {
+        let mut spec = NullDeviceSpec::new(NullDeviceOptions {
+            name: Some(format!("{} {}", name, suffix)),
+            input_channels: Some(input),
+            output_channels: Some(output),
+            sample_rates: Some(vec![sample_rate]),
+            sample_formats: options.sample_formats.clone(),
+            block_frames: options.block_frames,
+            frequency: None,
+        });
+        spec.route = route;
+        Arc::new(spec)
+    };
+    let drift = 1.0 + options.drift_ppm.unwrap_or(0.0) / 1e6;
+
+    [
+        NullDevice {
+            spec: spec("Output", 0, channels, Route::Sink(link.clone())),
+            speed,
+        },
+        NullDevice {
+            spec: spec("Input", channels, 0, Route::Source(link)),
+            speed: speed * drift,
+        },
+    ]
+}
+
+impl NullDeviceSpec {
+    fn new(options: NullDeviceOptions) -> Self {
+        let sample_rates = options
+            .sample_rates
+            .filter(|r| !r.is_empty())
+            .unwrap_or_else(|| vec![DEFAULT_SAMPLE_RATE]);
+        let sample_formats = options
+            .sample_formats
+            .filter(|f| !f.is_empty())
+            .map_or_else(
+                || vec![cpal::SampleFormat::F32],
+                |f| f.into_iter().map(Into::into).collect(),
+            );
+        NullDeviceSpec {
+            name: options
+                .name
+                .unwrap_or_else(|| DEFAULT_DEVICE_NAME.to_string()),
+            input_channels: options.input_channels.unwrap_or(DEFAULT_CHANNELS),
+            output_channels: options.output_channels.unwrap_or(DEFAULT_CHANNELS),
+            sample_rates,
+            sample_formats,
+            block_frames: options.block_frames.unwrap_or(DEFAULT_BLOCK_FRAMES).max(1),
+            frequency: options.frequency,
+            route: Route::None,
+        }
+    }
+
+    fn configs(&self, channels: u16) -> Vec<cpal::SupportedStreamConfigRange> {
+        if channels == 0 {
+            return Vec::new();
+        }
+        let buffer_size = cpal::SupportedBufferSize::Range {
+            min: 1,
+            max: u32::MAX,
+        };
+        self.sample_formats
+            .iter()
+            .flat_map(|format| {
+                self.sample_rates.iter().map(move |rate| {
+                    cpal::SupportedStreamConfigRange::new(
+                        channels,
+                        *rate,
+                        *rate,
+                        buffer_size,
+                        *format,
+                    )
+                })
+            })
+            .collect()
+    }
+
+    fn default_config(
+        &self,
+        channels: u16,
+    ) -> Result<cpal::SupportedStreamConfig, cpal::DefaultStreamConfigError> {
+        if channels == 0 {
+            return Err(cpal::DefaultStreamConfigError::StreamTypeNotSupported);
+        }
+        Ok(cpal::SupportedStreamConfig::new(
+            channels,
+            self.sample_rates[0],
+            cpal::SupportedBufferSize::Range {
+                min: 1,
+                max: u32::MAX,
+            },
+            self.sample_formats[0],
+        ))
+    }
+
+    /// Frames per block for `config`, or an error if the device cannot run it.
+    fn block_frames(
+        &self,
+        channels: u16,
+        config: &cpal::StreamConfig,
+        sample_format: cpal::SampleFormat,
+    ) -> Result<u32, cpal::BuildStreamError> {
+        if channels == 0
+            || config.channels != channels
+            || !self.sample_rates.contains(&config.sample_rate)
+            || !self.sample_formats.contains(&sample_format)
+        {
+            return Err(cpal::BuildStreamError::StreamConfigNotSupported);
+        }
+        match config.buffer_size {
+            cpal::BufferSize::Fixed(0) => Err(cpal::BuildStreamError::InvalidArgument),
+            cpal::BufferSize::Fixed(frames) => Ok(frames),
+            cpal::BufferSize::Default => Ok(self.block_frames),
+        }
+    }
+}
+
+struct NullHost {
+    devices: Arc<NullDevices>,
+}
+
+impl HostTrait for NullHost {
+    type Devices = std::vec::IntoIter<NullDevice>;
+    type Device = NullDevice;
+
+    fn is_available() -> bool {
+        true
+    }
+
+    fn devices(&self) -> Result<Self::Devices, cpal::DevicesError> {
+        Ok(self.devices.devices.lock().unwrap().clone().into_iter())
+    }
+
+    fn default_input_device(&self) -> Option<Self::Device> {
+        self.devices
+            .devices
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|d| d.spec.input_channels > 0)
+            .cloned()
+    }
+
+    fn default_output_device(&self) -> Option<Self::Device> {
+        self.devices
+            .devices
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|d| d.spec.output_channels > 0)
+            .cloned()
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct NullDevice {
+    spec: Arc<NullDeviceSpec>,
+    speed: f64,
+}
+
+impl NullDevice {
+    pub(crate) fn offline(options: NullDeviceOptions, engine: Arc<OfflineEngine>) -> Self {
+        let mut spec = NullDeviceSpec::new(options);
+        spec.route = Route::Offline(engine);
+        NullDevice {
+            spec: Arc::new(spec),
+            speed: 1.0,
+        }
+    }
+}
+
+impl DeviceTrait for NullDevice {
+    type SupportedInputConfigs = std::vec::IntoIter<cpal::SupportedStreamConfigRange>;
+    type SupportedOutputConfigs = std::vec::IntoIter<cpal::SupportedStreamConfigRange>;
+    type Stream = NullStream;
+
+    fn name(&self) -> Result<String, cpal::DeviceNameError> {
+        Ok(self.spec.name.clone())
+    }
+
+    fn description(&self) -> Result<cpal::DeviceDescription, cpal::DeviceNameError> {
+        let direction = match (self.spec.input_channels > 0, self.spec.output_channels > 0) {
+            (true, true) => cpal::DeviceDirection::Duplex,
+            (true, false) => cpal::DeviceDirection::Input,
+            (false, true) => cpal::DeviceDirection::Output,
+            (false, false) => cpal::DeviceDirection::Unknown,
+        };
+        Ok(cpal::DeviceDescriptionBuilder::new(self.spec.name.clone())
+            .direction(direction)
+            .interface_type(cpal::InterfaceType::Virtual)
+            .driver("null")
+            .build())
+    }
+
+    fn id(&self) -> Result<cpal::DeviceId, cpal::DeviceIdError> {
+        Ok(cpal::DeviceId(cpal::HostId::Custom, self.spec.name.clone()))
+    }
+
+    fn supports_input(&self) -> bool {
+        self.spec.input_channels > 0
+    }
+
+    fn supports_output(&self) -> bool {
+        self.spec.output_channels > 0
+    }
+
+    fn supported_input_configs(
+        &self,
+    ) -> Result<Self::SupportedInputConfigs, cpal::SupportedStreamConfigsError> {
+        Ok(self.spec.configs(self.spec.input_channels).into_iter())
+    }
+
+    fn supported_output_configs(
+        &self,
+    ) -> Result<Self::SupportedOutputConfigs, cpal::SupportedStreamConfigsError> {
+        Ok(self.spec.configs(self.spec.output_channels).into_iter())
+    }
+
+    fn default_input_config(
+        &self,
+    ) -> Result<cpal::SupportedStreamConfig, cpal::DefaultStreamConfigError> {
+        self.spec.default_config(self.spec.input_channels)
+    }
+
+    fn default_output_config(
+        &self,
+    ) -> Result<cpal::SupportedStreamConfig, cpal::DefaultStreamConfigError> {
+        self.spec.default_config(self.spec.output_channels)
+    }
+
+    fn build_input_stream_raw<D, E>(
+        &self,
+        config: &cpal::StreamConfig,
+        sample_format: cpal::SampleFormat,
+        mut data_callback: D,
+        _error_callback: E,
+        _timeout: Option<Duration>,
+    ) -> Result<Self::Stream, cpal::BuildStreamError>
+    where
+        D: FnMut(&cpal::Data, &cpal::InputCallbackInfo) + Send + 'static,
+        E: FnMut(cpal::StreamError) + Send + 'static,
+    {
+        let frames = self
+            .spec
+            .block_frames(self.spec.input_channels, config, sample_format)?;
+        let mut clock = SimulatedClock::new(config.sample_rate, frames);
+        let mut signal = Signal::new(self.spec.frequency, config);
+        let mut block = Block::new(frames as usize * config.channels as usize, sample_format);
+        let link = match &self.spec.route {
+            Route::Source(link) => Some(link.clone()),
+            _ => None,
+        };
+
+        Ok(NullStream::spawn(
+            clock.block_duration(self.speed),
+            move || {
+                match &link {
+                    Some(link) => {
+                        link.ring.pop_or_silence(&mut block.samples);
+                    }
+                    None => signal.fill(&mut block.samples),
+                }
+                let callback = clock.callback_instant();
+                let timestamp = cpal::InputStreamTimestamp {
+                    callback,
+                    capture: clock.block_start(),
+                };
+                data_callback(&block.data(), &cpal::InputCallbackInfo::new(timestamp));
+                clock.advance();
+            },
+        ))
+    }
+
+    fn build_output_stream_raw<D, E>(
+        &self,
+        config: &cpal::StreamConfig,
+        sample_format: cpal::SampleFormat,
+        mut data_callback: D,
+        _error_callback: E,
+        _timeout: Option<Duration>,
+    ) -> Result<Self::Stream, cpal::BuildStreamError>
+    where
+        D: FnMut(&mut cpal::Data, &cpal::OutputCallbackInfo) + Send + 'static,
+        E: FnMut(cpal::StreamError) + Send + 'static,
+    {
+        let frames = self
+            .spec
+            .block_frames(self.spec.output_channels, config, sample_format)?;
+        let mut clock = SimulatedClock::new(config.sample_rate, frames);
+        let mut block = Block::new(frames as usize * config.channels as usize, sample_format);
+        let link = match &self.spec.route {
+            Route::Sink(link) => Some(link.clone()),
+            _ => None,
+        };
+        let duration = clock.block_duration(self.speed);
+        let mut process = move |samples: Option<&mut Vec<f32>>| {
+            let callback = clock.callback_instant();
+            let timestamp = cpal::OutputStreamTimestamp {
+                callback,
+                playback: clock.block_end(),
+            };
+            let mut data = block.data();
+            data_callback(&mut data, &cpal::OutputCallbackInfo::new(timestamp));
+            if link.is_some() || samples.is_some() {
+                sample::read_data(&data, &mut block.samples);
+            }
+            if let Some(link) = &link {
+                link.ring.push_slice(&block.samples);
+            }
+            if let Some(samples) = samples {
+                samples.extend_from_slice(&block.samples);
+            }
+            clock.advance();
+        };
+
+        match &self.spec.route {
+            Route::Offline(engine) => {
+                let voice = Voice::new(Box::new(move |out| process(Some(out))));
+                engine.register(&voice);
+                Ok(NullStream::offline(voice))
+            }
+            _ => Ok(NullStream::spawn(duration, move || process(None))),
+        }
+    }
+}
+
+/// Stream time derived from the number of frames processed rather than the wall clock.
+///
+/// Each callback runs at the end of the block it captures (input) and one block before
+/// the audio it renders is played (output).
+struct SimulatedClock {
+    sample_rate: u32,
+    block_frames: u32,
+    frames: u64,
+}
+
+impl SimulatedClock {
+    fn new(sample_rate: u32, block_frames: u32) -> Self {
+        SimulatedClock {
+            sample_rate: sample_rate.max(1),
+            block_frames,
+            frames: 0,
+        }
+    }
+
+    fn block_duration(&self, speed: f64) -> Duration {
+        Duration::from_secs_f64(self.block_frames as f64 / self.sample_rate as f64 / speed)
+    }
+
+    fn instant(&self, frames: u64) -> cpal::StreamInstant {
+        let rate = self.sample_rate as u64;
+        let nanos = (frames % rate) * 1_000_000_000 / rate;
+        cpal::StreamInstant::new((frames / rate) as i64, nanos as u32)
+    }
+
+    fn block_start(&self) -> cpal::StreamInstant {
+        self.instant(self.frames)
+    }
+
+    fn callback_instant(&self) -> cpal::StreamInstant {
+        self.instant(self.frames + self.block_frames as u64)
+    }
+
+    fn block_end(&self) -> cpal::StreamInstant {
+        self.instant(self.frames + 2 * self.block_frames as u64)
+    }
+
+    fn advance(&mut self) {
+        self.frames += self.block_frames as u64;
+    }
+}
+
+/// What a null input captures.
+struct Signal {
+    frequency: Option<f64>,
+    sample_rate: f64,
+    channels: usize,
+    phase: f64,
+}
+
+impl Signal {
+    fn new(frequency: Option<f64>, config: &cpal::StreamConfig) -> Self {
+        Signal {
+            frequency,
+            sample_rate: config.sample_rate.max(1) as f64,
+            channels: config.channels.max(1) as usize,
+            phase: 0.0,
+        }
+    }
+
+    fn fill(&mut self, out: &mut [f32]) {
+        let Some(frequency) = self.frequency else {
+            out.fill(0.0);
+            return;
+        };
+        let step = frequency / self.sample_rate;
+        for frame in out.chunks_mut(self.channels) {
+            let value = (self.phase * std::f64::consts::TAU).sin() * 0.5;
+            frame.fill(value as f32);
+            self.phase = (self.phase + step).fract();
+        }
+    }
+}
+
+/// One block of samples in `f32` plus storage for the device format.
+struct Block {
+    samples: Vec<f32>,
+    // u64 words keep the storage aligned for every sample type.
+    storage: Vec<u64>,
+    sample_format: cpal::SampleFormat,
+}
+
+impl Block {
+    fn new(len: usize, sample_format: cpal::SampleFormat) -> Self {
+        Block {
+            samples: vec![0.0; len],
+            storage: vec![0; (len * sample_format.sample_size()).div_ceil(8)],
+            sample_format,
+        }
+    }
+
+    /// The block in the device format, converted from `samples`.
+    fn data(&mut self) -> cpal::Data {
+        // SAFETY: `storage` is aligned to 8 bytes and holds `samples.len()` samples of
+        // `sample_format`; the `Data` never outlives the callback it is passed to.
+        let mut data = unsafe {
+            cpal::Data::from_parts(
+                self.storage.as_mut_ptr().cast(),
+                self.samples.len(),
+                self.sample_format,
+            )
+        };
+        sample::write_data(&self.samples, &mut data);
+        data
+    }
+}
+
+struct Transport {
+    playing: bool,
+    exit: bool,
+}
+
+/// Runs a block callback on its own thread, paced by the wall clock, while playing,
+/// or leaves it to an offline renderer.
+pub(crate) struct NullStream {
+    transport: Arc<(Mutex<Transport>, Condvar)>,
+    handle: Option<JoinHandle<()>>,
+    voice: Option<Arc<Voice>>,
+}
+
+impl NullStream {
+    fn offline(voice: Arc<Voice>) -> Self {
+        NullStream {
+            transport: Arc::new((
+                Mutex::new(Transport {
+                    playing: false,
+                    exit: false,
+                }),
+                Condvar::new(),
+            )),
+            handle: None,
+            voice: Some(voice),
+        }
+    }
+
+    fn spawn<F>(block_duration: Duration, mut process: F) -> Self
+    where
+        F: FnMut() + Send + 'static,
+    {
+        let transport = Arc::new((
+            Mutex::new(Transport {
+                playing: false,
+                exit: false,
+            }),
+            Condvar::new(),
+        ));
+        let shared = transport.clone();
+        let handle = std::thread::spawn(move || {
+            let (lock, cvar) = &*shared;
+            let mut deadline: Option<Instant> = None;
+            loop {
+                let mut state = lock.lock().unwrap();
+                if !state.playing {
+                    deadline = None;
+                    state = cvar.wait_while(state, |s| !s.playing && !s.exit).unwrap();
+                }
+                if state.exit {
+                    return;
+                }
+                let next = deadline.unwrap_or_else(Instant::now) + block_duration;
+                let timeout = next.saturating_duration_since(Instant::now());
+                let (state, _) = cvar
+                    .wait_timeout_while(state, timeout, |s| s.playing && !s.exit)
+                    .unwrap();
+                if state.exit {
+                    return;
+                }
+                if !state.playing {
+                    continue;
+                }
+                drop(state);
+
+                process();
+                deadline = Some(next);
+            }
+        });
+
+        NullStream {
+            transport,
+            handle: Some(handle),
+            voice: None,
+        }
+    }
+
+    fn set_playing(&self, playing: bool) {
+        let (lock, cvar) = &*self.transport;
+        lock.lock().unwrap().playing = playing;
+        cvar.notify_all();
+        if let Some(voice) = &self.voice {
+            voice.playing.store(playing, Ordering::Release);
+        }
+    }
+}
+
+impl StreamTrait for NullStream {
+    fn play(&self) -> Result<(), cpal::PlayStreamError> {
+        self.set_playing(true);
+        Ok(())
+    }
+
+    fn pause(&self) -> Result<(), cpal::PauseStreamError> {
+        self.set_playing(false);
+        Ok(())
+    }
+}
+
+impl Drop for NullStream {
+    fn drop(&mut self) {
+        let (lock, cvar) = &*self.transport;
+        lock.lock().unwrap().exit = true;
+        cvar.notify_all();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    fn null_device(options: NullDeviceOptions) -> cpal::Device {
+        create_null_host(Some(NullHostOptions {
+            devices: Some(vec![options]),
+            speed: Some(50.0),
+            ..Default::default()
+        }))
+        .inner
+        .devices()
+        .unwrap()
+        .next()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_loopback_carries_output_to_input() {
+        let [output, input] = loopback_pair(
+            LoopbackOptions {
+                channels: Some(1),
+                latency_frames: Some(2),
+                ..Default::default()
+            },
+            50.0,
+        );
+        let output = cpal::Device::from(cpal::platform::CustomDevice::from_device(output));
+        let input = cpal::Device::from(cpal::platform::CustomDevice::from_device(input));
+        let config = output.default_output_config().unwrap().config();
+
+        let captured = Arc::new(Mutex::new(Vec::new()));
+        let sink = captured.clone();
+        let playback = output
+            .build_output_stream(
+                &config,
+                |data: &mut [f32], _: &_| data.fill(0.25),
+                |_| {},
+                None,
+            )
+            .unwrap();
+        let capture = input
+            .build_input_stream(
+                &config,
+                move |data: &[f32], _: &_| sink.lock().unwrap().extend_from_slice(data),
+                |_| {},
+                None,
+            )
+            .unwrap();
+        playback.play().unwrap();
+        capture.play().unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !captured.lock().unwrap().contains(&0.25) && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        drop(capture);
+
+        let captured = captured.lock().unwrap();
+        assert!(
+            captured.len() >= 2,
+            "only {} samples captured",
+            captured.len()
+        );
+        assert_eq!(captured[..2], [0.0, 0.0]);
+        assert!(captured.contains(&0.25));
+    }
+
+    #[test]
+    fn test_simulated_clock() {
+        let mut clock = SimulatedClock::new(48000, 480);
+        clock.advance();
+        let start = cpal::StreamInstant::new(0, 0);
+        assert_eq!(
+            clock.block_start().duration_since(&start),
+            Some(Duration::from_millis(10))
+        );
+        assert_eq!(
+            clock.block_end().duration_since(&start),
+            Some(Duration::from_millis(30))
+        );
+    }
+
+    #[test]
+    fn test_default_configs() {
+        let device = null_device(NullDeviceOptions {
+            output_channels: Some(1),
+            sample_rates: Some(vec![44100, 48000]),
+            ..Default::default()
+        });
+        let config = device.default_output_config().unwrap();
+        assert_eq!(config.channels(), 1);
+        assert_eq!(config.sample_rate(), 44100);
+        assert_eq!(device.supported_output_configs().unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_rejects_unsupported_config() {
+        let device = null_device(NullDeviceOptions::default());
+        let config = cpal::StreamConfig {
+            channels: 2,
+            sample_rate: 22050,
+            buffer_size: cpal::BufferSize::Default,
+        };
+        let result = device.build_output_stream(&config, |_: &mut [f32], _: &_| {}, |_| {}, None);
+        assert!(matches!(
+            result,
+            Err(cpal::BuildStreamError::StreamConfigNotSupported)
+        ));
+    }
+
+    #[test]
+    fn test_input_stream_runs_while_playing() {
+        let device = null_device(NullDeviceOptions {
+            frequency: Some(440.0),
+            ..Default::default()
+        });
+        let config = device.default_input_config().unwrap().config();
+        let blocks = Arc::new(AtomicUsize::new(0));
+        let counter = blocks.clone();
+        let stream = device
+            .build_input_stream(
+                &config,
+                move |data: &[f32], _: &_| {
+                    assert_eq!(data.len(), 960);
+                    counter.fetch_add(1, Ordering::Relaxed);
+                },
+                |_| {},
+                None,
+            )
+            .unwrap();
+
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(blocks.load(Ordering::Relaxed), 0);
+
+        stream.play().unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while blocks.load(Ordering::Relaxed) == 0 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        stream.pause().unwrap();
+        assert!(blocks.load(Ordering::Relaxed) > 0);
+    }
+}
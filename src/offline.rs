@@ -0,0 +1,235 @@
+use crate::device::AudioDevice;
+use crate::error::{io_error, join_error, spawn_coded};
+use crate::null_host::{NullDevice, NullDeviceOptions};
+use crate::types::SampleFormat;
+use crate::wav::{WavFormat, WavWriter};
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+const DEFAULT_CHANNELS: u16 = 2;
+const DEFAULT_SAMPLE_RATE: u32 = 48000;
+
+#[napi(object)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OfflineOptions {
+    pub channels: Option<u16>,
+    pub sample_rate: Option<u32>,
+    /// Format the streams render in before conversion back to `f32` (default `F32`).
+    pub sample_format: Option<SampleFormat>,
+    pub block_frames: Option<u32>,
+}
+
+/// Appends the next block a stream renders, as `f32`.
+pub(crate) type RenderBlock = Box<dyn FnMut(&mut Vec<f32>) + Send>;
+
+/// One output stream built on an offline device.
+pub(crate) struct Voice {
+    pub(crate) playing: AtomicBool,
+    render: Mutex<RenderBlock>,
+    /// Rendered samples not yet mixed, when blocks do not line up with a render call.
+    carry: Mutex<Vec<f32>>,
+}
+
+impl Voice {
+    pub(crate) fn new(render: RenderBlock) -> Arc<Self> {
+        Arc::new(Voice {
+            playing: AtomicBool::new(false),
+            render: Mutex::new(render),
+            carry: Mutex::new(Vec::new()),
+        })
+    }
+
+    fn mix_into(&self, mix: &mut [f32]) {
+        let mut carry = self.carry.lock().unwrap();
+        let mut render = self.render.lock().unwrap();
+        while carry.len() < mix.len() {
+            let before = carry.len();
+            render(&mut carry);
+            if carry.len() == before {
+                break;
+            }
+        }
+        let count = carry.len().min(mix.len());
+        for (out, sample) in mix.iter_mut().zip(carry.drain(..count)) {
+            *out += sample;
+        }
+    }
+}
+
+/// Pulls audio from the streams of an offline device on demand.
+pub(crate) struct OfflineEngine {
+    channels: u16,
+    voices: Mutex<Vec<Weak<Voice>>>,
+    position: AtomicU64,
+}
+
+impl OfflineEngine {
+    pub(crate) fn register(&self, voice: &Arc<Voice>) {
+        let mut voices = self.voices.lock().unwrap();
+        voices.retain(|v| v.strong_count() > 0);
+        voices.push(Arc::downgrade(voice));
+    }
+
+    /// Mixes `frames` frames of every playing stream.
+    fn render(&self, frames: u32) -> Vec<f32> {
+        let mut mix = vec![0.0; frames as usize * self.channels as usize];
+        let voices: Vec<Arc<Voice>> = self
+            .voices
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(Weak::upgrade)
+            .collect();
+        for voice in voices {
+            if voice.playing.load(Ordering::Acquire) {
+                voice.mix_into(&mut mix);
+            }
+        }
+        self.position.fetch_add(frames as u64, Ordering::Relaxed);
+        mix
+    }
+}
+
+/// Runs output streams faster than real time, without an audio device.
+///
+/// Streams built on `device()` go through the same code as on real hardware but are
+/// only advanced by `render()`, one block at a time, into memory or a WAV file.
+#[napi]
+pub struct OfflineRenderer {
+    engine: Arc<OfflineEngine>,
+    device: NullDevice,
+    sample_rate: u32,
+}
+
+#[napi]
+impl OfflineRenderer {
+    #[napi(constructor)]
+    pub fn new(options: Option<OfflineOptions>) -> Self {
+        let options = options.unwrap_or_default();
+        let channels = options.channels.unwrap_or(DEFAULT_CHANNELS).max(1);
+        let sample_rate = options.sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE).max(1);
+        let engine = Arc::new(OfflineEngine {
+            channels,
+            voices: Mutex::new(Vec::new()),
+            position: AtomicU64::new(0),
+        });
+        let device = NullDevice::offline(
+            NullDeviceOptions {
+                name: Some("Offline Renderer".to_string()),
+                input_channels: Some(0),
+                output_channels: Some(channels),
+                sample_rates: Some(vec![sample_rate]),
+                sample_formats: options.sample_format.map(|f| vec![f]),
+                block_frames: options.block_frames,
+                frequency: None,
+            },
+            engine.clone(),
+        );
+        OfflineRenderer {
+            engine,
+            device,
+            sample_rate,
+        }
+    }
+
+    /// A device whose output streams render into this renderer.
+    #[napi]
+    pub fn device(&self) -> AudioDevice {
+        AudioDevice {
+            inner: cpal::Device::from(cpal::platform::CustomDevice::from_device(
+                self.device.clone(),
+            )),
+            realtime: false,
+        }
+    }
+
+    #[napi(getter)]
+    pub fn channels(&self) -> u16 {
+        self.engine.channels
+    }
+
+    #[napi(getter)]
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Frames rendered so far.
+    #[napi(getter)]
+    pub fn position(&self) -> i64 {
+        self.engine.position.load(Ordering::Relaxed) as i64
+    }
+
+    /// Renders the next `frames` frames of every playing stream, mixed together.
+    #[napi(ts_return_type = "Promise<Float32Array>")]
+    pub fn render<'env>(
+        &self,
+        env: &'env Env,
+        frames: u32,
+    ) -> Result<PromiseRaw<'env, Float32Array>> {
+        let engine = self.engine.clone();
+        spawn_coded(env, async move {
+            let mix = tokio::task::spawn_blocking(move || engine.render(frames))
+                .await
+                .map_err(join_error)?;
+            Ok(Float32Array::new(mix))
+        })
+    }
+
+    /// Renders the next `frames` frames into a 32-bit float WAV file at `path`.
+    #[napi(ts_return_type = "Promise<void>")]
+    pub fn render_to_file<'env>(
+        &self,
+        env: &'env Env,
+        path: String,
+        frames: u32,
+    ) -> Result<PromiseRaw<'env, ()>> {
+        let engine = self.engine.clone();
+        let sample_rate = self.sample_rate;
+        spawn_coded(env, async move {
+            tokio::task::spawn_blocking(move || {
+                let mix = engine.render(frames);
+                let write = || -> std::io::Result<()> {
+                    let mut writer =
+                        WavWriter::create(&path, engine.channels, sample_rate, WavFormat::Float32)?;
+                    writer.write(&mix)?;
+                    writer.finalize()
+                };
+                write().map_err(io_error(format!("Failed to write {}", path)))
+            })
+            .await
+            .map_err(join_error)?
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mixes_playing_voices() {
+        let engine = OfflineEngine {
+            channels: 1,
+            voices: Mutex::new(Vec::new()),
+            position: AtomicU64::new(0),
+        };
+        let constant = |value: f32| Voice::new(Box::new(move |out| out.extend([value; 3])));
+        let a = constant(0.25);
+        let b = constant(0.5);
+        let silent = constant(1.0);
+        engine.register(&a);
+        engine.register(&b);
+        engine.register(&silent);
+        a.playing.store(true, Ordering::Release);
+        b.playing.store(true, Ordering::Release);
+
+        assert_eq!(engine.render(4), vec![0.75; 4]);
+        assert_eq!(a.carry.lock().unwrap().len(), 2);
+        assert_eq!(engine.position.load(Ordering::Relaxed), 4);
+
+        drop(b);
+        assert_eq!(engine.render(2), vec![0.25; 2]);
+    }
+}
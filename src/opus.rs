@@ -0,0 +1,214 @@
+use crate::channels::ChannelMapping;
+use crate::resampler::{ResampleQuality, Resampler};
+use audiopus::coder::Encoder;
+use audiopus::{Application, Bitrate, Channels, SampleRate};
+use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+use std::io::{self, Write};
+
+/// Opus always runs at 48 kHz; other input rates are resampled first.
+const OPUS_RATE: u32 = 48000;
+/// 20 ms, the frame size libopus is tuned for.
+const FRAME_SIZE: usize = 960;
+const MAX_PACKET_BYTES: usize = 4000;
+/// Packets per Ogg page, so streamed output arrives about once a second.
+const PACKETS_PER_PAGE: u64 = 50;
+const SERIAL: u32 = 0x6370_616c;
+const VENDOR: &str = concat!("cpal-napi ", env!("CARGO_PKG_VERSION"));
+
+/// Encodes interleaved `f32` samples to Opus in an Ogg container (RFC 7845).
+///
+/// Mono input stays mono; anything wider is coded as stereo from its first two channels.
+pub(crate) struct OpusEncoder<W: Write> {
+    ogg: PacketWriter<W>,
+    encoder: Encoder,
+    input_channels: usize,
+    channels: usize,
+    resampler: Resampler,
+    frame: Vec<f32>,
+    mapped: Vec<f32>,
+    pending: Vec<f32>,
+    packet: Vec<u8>,
+    pre_skip: u64,
+    packets: u64,
+    /// Frames of real input so far, at 48 kHz.
+    frames: u64,
+}
+
+impl<W: Write> OpusEncoder<W> {
+    /// Writes the identification and comment headers.
+    pub(crate) fn new(
+        out: W,
+        channels: u16,
+        sample_rate: u32,
+        bitrate: Option<u32>,
+    ) -> io::Result<Self> {
+        let opus_channels = if channels > 1 { 2 } else { 1 };
+        let mut encoder = Encoder::new(
+            SampleRate::Hz48000,
+            if opus_channels == 2 {
+                Channels::Stereo
+            } else {
+                Channels::Mono
+            },
+            Application::Audio,
+        )
+        .map_err(opus_error)?;
+        if let Some(bitrate) = bitrate {
+            encoder
+                .set_bitrate(Bitrate::BitsPerSecond(bitrate as i32))
+                .map_err(opus_error)?;
+        }
+        let pre_skip = encoder.lookahead().map_err(opus_error)? as u64;
+
+        let mut head = b"OpusHead".to_vec();
+        head.push(1);
+        head.push(opus_channels as u8);
+        head.extend((pre_skip as u16).to_le_bytes());
+        head.extend(sample_rate.to_le_bytes());
+        head.extend(0i16.to_le_bytes());
+        head.push(0);
+        let mut tags = b"OpusTags".to_vec();
+        tags.extend((VENDOR.len() as u32).to_le_bytes());
+        tags.extend(VENDOR.as_bytes());
+        tags.extend(0u32.to_le_bytes());
+
+        let mut ogg = PacketWriter::new(out);
+        ogg.write_packet(head.into(), SERIAL, PacketWriteEndInfo::EndPage, 0)?;
+        ogg.write_packet(tags.into(), SERIAL, PacketWriteEndInfo::EndPage, 0)?;
+        Ok(OpusEncoder {
+            ogg,
+            encoder,
+            input_channels: channels.max(1) as usize,
+            channels: opus_channels,
+            resampler: Resampler::new(
+                opus_channels as u16,
+                sample_rate,
+                OPUS_RATE,
+                ResampleQuality::High,
+            ),
+            frame: vec![0.0; opus_channels],
+            mapped: Vec::new(),
+            pending: Vec::new(),
+            packet: vec![0; MAX_PACKET_BYTES],
+            pre_skip,
+            packets: 0,
+            frames: 0,
+        })
+    }
+
+    pub(crate) fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        let before = self.pending.len();
+        if self.input_channels == self.channels {
+            self.resampler.process(samples, &mut self.pending);
+        } else {
+            self.mapped.clear();
+            for source in samples.chunks_exact(self.input_channels) {
+                ChannelMapping::Duplicate.map_frame(source, &mut self.frame);
+                self.mapped.extend_from_slice(&self.frame);
+            }
+            self.resampler.process(&self.mapped, &mut self.pending);
+        }
+        self.frames += ((self.pending.len() - before) / self.channels) as u64;
+        self.encode_pending(0)
+    }
+
+    /// Pads the input with silence past the encoder's lookahead, ends the Ogg stream and
+    /// flushes the output.
+    pub(crate) fn finish(&mut self) -> io::Result<()> {
+        let before = self.pending.len();
+        self.resampler.flush(&mut self.pending);
+        self.frames += ((self.pending.len() - before) / self.channels) as u64;
+        // The encoder lags `pre_skip` samples behind its input, so the real end only comes
+        // out once that much silence follows it.
+        let block = FRAME_SIZE * self.channels;
+        let padded = self.pending.len() + self.pre_skip as usize * self.channels;
+        self.pending.resize(padded.div_ceil(block) * block, 0.0);
+        // Keep the last frame back for the end-of-stream packet.
+        self.encode_pending(block)?;
+        self.encode(0, PacketWriteEndInfo::EndStream)?;
+        self.pending.clear();
+        self.ogg.inner_mut().flush()
+    }
+
+    /// Encodes whole frames from `pending` while more than `keep` samples would remain.
+    fn encode_pending(&mut self, keep: usize) -> io::Result<()> {
+        let block = FRAME_SIZE * self.channels;
+        let mut start = 0;
+        while self.pending.len() - start >= block + keep {
+            self.encode(start, PacketWriteEndInfo::NormalPacket)?;
+            start += block;
+        }
+        self.pending.drain(..start);
+        Ok(())
+    }
+
+    fn encode(&mut self, start: usize, end: PacketWriteEndInfo) -> io::Result<()> {
+        let input = &self.pending[start..start + FRAME_SIZE * self.channels];
+        let len = self
+            .encoder
+            .encode_float(input, &mut self.packet)
+            .map_err(opus_error)?;
+        self.packets += 1;
+        // The last page's granule position trims the padding off the final frame.
+        let (granule, end) = match end {
+            PacketWriteEndInfo::EndStream => (self.pre_skip + self.frames, end),
+            _ if self.packets.is_multiple_of(PACKETS_PER_PAGE) => (
+                self.packets * FRAME_SIZE as u64,
+                PacketWriteEndInfo::EndPage,
+            ),
+            _ => (self.packets * FRAME_SIZE as u64, end),
+        };
+        self.ogg
+            .write_packet(self.packet[..len].into(), SERIAL, end, granule)?;
+        if end != PacketWriteEndInfo::NormalPacket {
+            self.ogg.inner_mut().flush()?;
+        }
+        Ok(())
+    }
+}
+
+fn opus_error(err: audiopus::Error) -> io::Error {
+    io::Error::other(format!("Opus encoder: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_writes_ogg_opus_headers_and_pages() {
+        let mut encoder = OpusEncoder::new(Vec::new(), 2, 44100, Some(64000)).unwrap();
+        let tone: Vec<f32> = (0..44100)
+            .flat_map(|i| {
+                let value = (i as f32 * 440.0 * std::f32::consts::TAU / 44100.0).sin() * 0.5;
+                [value, value]
+            })
+            .collect();
+        encoder.write(&tone).unwrap();
+        encoder.finish().unwrap();
+        let bytes = encoder.ogg.into_inner();
+
+        assert_eq!(&bytes[..4], b"OggS");
+        assert_eq!(&bytes[28..36], b"OpusHead");
+        assert_eq!(bytes[37], 2);
+        // Last page carries the end-of-stream flag.
+        let last = bytes.windows(4).rposition(|w| w == b"OggS").unwrap();
+        assert_eq!(bytes[last + 5] & 0x04, 0x04);
+    }
+
+    #[test]
+    fn test_end_granule_stays_within_encoded_samples() {
+        // A last partial frame longer than 960 - pre_skip needs an extra packet to flush.
+        let mut encoder = OpusEncoder::new(Vec::new(), 1, OPUS_RATE, None).unwrap();
+        let frames = FRAME_SIZE * 10 + 900;
+        encoder.write(&vec![0.25; frames]).unwrap();
+        encoder.finish().unwrap();
+        let (packets, pre_skip) = (encoder.packets, encoder.pre_skip);
+        let bytes = encoder.ogg.into_inner();
+
+        let last = bytes.windows(4).rposition(|w| w == b"OggS").unwrap();
+        let granule = u64::from_le_bytes(bytes[last + 6..last + 14].try_into().unwrap());
+        assert_eq!(granule, pre_skip + frames as u64);
+        assert!(granule <= packets * FRAME_SIZE as u64);
+    }
+}
@@ -0,0 +1,418 @@
+use crate::callback::OFFLINE_WAIT;
+use crate::channels::ChannelMapping;
+use crate::config::StreamConfig;
+use crate::decoder::FileDecoder;
+use crate::error::{io_error, ErrorCode};
+use crate::resampler::{ResampleQuality, Resampler};
+use crate::ring::RingBuffer;
+use crate::stream::StreamStatus;
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Seconds of converted audio the decode thread keeps ahead of the device.
+const PLAYBACK_QUEUE_SECONDS: f64 = 0.5;
+const FILL_INTERVAL: Duration = Duration::from_millis(5);
+const NO_SEEK: u64 = u64::MAX;
+
+/// What a file contains, read from its headers without decoding the audio.
+#[napi(object)]
+#[derive(Debug, Clone, Default)]
+pub struct AudioFileInfo {
+    /// Short codec name, e.g. `flac`, `vorbis`, `mp3` or `pcm_s16le`.
+    pub codec: String,
+    pub channels: u16,
+    pub sample_rate: u32,
+    /// Length in frames, when the file declares it.
+    pub frames: Option<i64>,
+    /// Length in seconds, when the file declares it.
+    pub duration: Option<f64>,
+    /// `title`, `artist`, `album`, `albumArtist`, `genre`, `date`, `trackNumber` and
+    /// `comment` when present, other tags under the name the file uses.
+    pub tags: HashMap<String, String>,
+}
+
+/// Reads the format, length and tags of an audio file.
+#[napi]
+pub fn probe_audio_file(path: String) -> Result<AudioFileInfo, ErrorCode> {
+    let decoder = FileDecoder::open(&path).map_err(io_error(format!("Failed to open {}", path)))?;
+    Ok(decoder.info().clone())
+}
+
+#[napi(object)]
+#[derive(Clone, Copy, Default)]
+pub struct PlayFileOptions {
+    /// Stream configuration to open; the device's default output config if omitted.
+    pub config: Option<StreamConfig>,
+    /// Start over from the beginning of the file instead of ending.
+    #[napi(js_name = "loop")]
+    pub looping: Option<bool>,
+    /// Seconds into the file to start playing from.
+    pub start_at: Option<f64>,
+    /// How file channels map onto the stream's; `Average` when downmixing, else `Duplicate`.
+    pub mapping: Option<ChannelMapping>,
+}
+
+/// Converted samples waiting for the output callback, and whether more will come.
+pub(crate) struct PlaybackQueue {
+    ring: RingBuffer,
+    finished: AtomicBool,
+    /// Seconds to seek to, as `f64` bits, or `NO_SEEK`.
+    seek_to: AtomicU64,
+}
+
+impl PlaybackQueue {
+    /// Asks the decode thread to continue from `seconds`, even after the end of the file.
+    pub(crate) fn seek(&self, seconds: f64) {
+        self.finished.store(false, Ordering::Release);
+        self.seek_to
+            .store(seconds.max(0.0).to_bits(), Ordering::Release);
+    }
+
+    fn take_seek(&self) -> Option<f64> {
+        match self.seek_to.swap(NO_SEEK, Ordering::AcqRel) {
+            NO_SEEK => None,
+            bits => Some(f64::from_bits(bits)),
+        }
+    }
+
+    /// Fills `data` from the queue, padding with silence; returns `true` once the file
+    /// has been played to the end.
+    ///
+    /// The decode thread tells the stream about the end, so callbacks can ignore the result.
+    ///
+    /// Offline streams wait for the decode thread instead of padding.
+    pub(crate) fn fill(&self, data: &mut [f32], realtime: bool) -> bool {
+        if !realtime {
+            let deadline = Instant::now() + OFFLINE_WAIT;
+            while self.ring.len() < data.len()
+                && !self.finished.load(Ordering::Acquire)
+                && Instant::now() < deadline
+            {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+        let finished = self.finished.load(Ordering::Acquire);
+        let count = self.ring.pop_slice(data);
+        data[count..].fill(0.0);
+        finished && self.ring.len() == 0
+    }
+}
+
+/// Decodes a file on its own thread, converting it to the stream's channel count and
+/// sample rate ahead of the output callback.
+///
+/// The thread also reports decoding errors and the end of playback to the stream's status,
+/// keeping locks and JavaScript calls off the audio thread.
+pub(crate) struct FilePlayer {
+    queue: Arc<PlaybackQueue>,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl FilePlayer {
+    pub(crate) fn spawn(
+        mut decoder: FileDecoder,
+        channels: u16,
+        sample_rate: u32,
+        quality: ResampleQuality,
+        options: PlayFileOptions,
+        status: Arc<StreamStatus>,
+    ) -> io::Result<Self> {
+        if let Some(start_at) = options.start_at.filter(|s| *s > 0.0) {
+            decoder.seek(start_at)?;
+        }
+        let capacity =
+            (sample_rate as f64 * channels as f64 * PLAYBACK_QUEUE_SECONDS).ceil() as usize;
+        let queue = Arc::new(PlaybackQueue {
+            ring: RingBuffer::new(capacity),
+            finished: AtomicBool::new(false),
+            seek_to: AtomicU64::new(NO_SEEK),
+        });
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut converter = Converter::new(
+            decoder.channels(),
+            decoder.sample_rate(),
+            channels,
+            sample_rate,
+            quality,
+            options.mapping,
+        );
+        let looping = options.looping.unwrap_or(false);
+        let (target, stopped) = (queue.clone(), stop.clone());
+        let handle = std::thread::spawn(move || {
+            let mut decoded = Vec::new();
+            let mut pending = Vec::new();
+            let mut offset = 0;
+            let mut played_since_seek = false;
+            let mut exhausted = false;
+            // Whether `finished` was set since the last seek; set once, so a seek racing
+            // with the end is not overwritten.
+            let mut announced = false;
+            // Whether `onEnded` went out since the last seek.
+            let mut reported = false;
+            while !stopped.load(Ordering::Acquire) {
+                if let Some(seconds) = target.take_seek() {
+                    if let Err(err) = decoder.seek(seconds) {
+                        status.report_error(format!("Failed to seek in audio file: {}", err));
+                    }
+                    converter.reset();
+                    pending.clear();
+                    offset = 0;
+                    exhausted = false;
+                    announced = false;
+                    reported = false;
+                    target.ring.clear();
+                    continue;
+                }
+                if offset == pending.len() {
+                    if exhausted {
+                        // The flushed tail has been queued; stay around so a seek can
+                        // restart playback.
+                        if !announced {
+                            target.finished.store(true, Ordering::Release);
+                            announced = true;
+                        }
+                        // A seek clears `finished`, so an end it replaced is not reported.
+                        if !reported
+                            && target.ring.len() == 0
+                            && target.finished.load(Ordering::Acquire)
+                        {
+                            status.report_ended();
+                            reported = true;
+                        }
+                        std::thread::sleep(FILL_INTERVAL);
+                        continue;
+                    }
+                    pending.clear();
+                    offset = 0;
+                    decoded.clear();
+                    match decoder.next_block(&mut decoded) {
+                        Ok(true) => {
+                            played_since_seek = true;
+                            converter.process(&decoded, &mut pending);
+                        }
+                        // Stop looping files that decode to nothing.
+                        Ok(false) if looping && played_since_seek => {
+                            played_since_seek = false;
+                            if let Err(err) = decoder.seek(0.0) {
+                                status.report_error(format!("Failed to loop audio file: {}", err));
+                                exhausted = true;
+                            }
+                        }
+                        Ok(false) => exhausted = true,
+                        Err(err) => {
+                            status.report_error(format!("Stopped decoding audio file: {}", err));
+                            exhausted = true;
+                        }
+                    }
+                    if exhausted {
+                        converter.flush(&mut pending);
+                    }
+                    continue;
+                }
+                let free = target.ring.capacity() - target.ring.len();
+                if free == 0 {
+                    std::thread::sleep(FILL_INTERVAL);
+                    continue;
+                }
+                let end = pending.len().min(offset + free);
+                offset += target.ring.push_slice(&pending[offset..end]);
+            }
+        });
+        Ok(FilePlayer {
+            queue,
+            stop,
+            handle: Some(handle),
+        })
+    }
+
+    pub(crate) fn queue(&self) -> Arc<PlaybackQueue> {
+        self.queue.clone()
+    }
+}
+
+impl Drop for FilePlayer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Maps file frames onto the stream's channels, then converts the sample rate.
+struct Converter {
+    mapping: ChannelMapping,
+    from_channels: usize,
+    to_channels: usize,
+    resampler: Resampler,
+    mapped: Vec<f32>,
+}
+
+impl Converter {
+    fn new(
+        from_channels: u16,
+        from_rate: u32,
+        to_channels: u16,
+        to_rate: u32,
+        quality: ResampleQuality,
+        mapping: Option<ChannelMapping>,
+    ) -> Self {
+        let mapping = mapping.unwrap_or(if to_channels < from_channels {
+            ChannelMapping::Average
+        } else {
+            ChannelMapping::Duplicate
+        });
+        Converter {
+            mapping,
+            from_channels: from_channels.max(1) as usize,
+            to_channels: to_channels.max(1) as usize,
+            resampler: Resampler::new(to_channels, from_rate, to_rate, quality),
+            mapped: Vec::new(),
+        }
+    }
+
+    fn process(&mut self, input: &[f32], out: &mut Vec<f32>) {
+        if self.from_channels == self.to_channels {
+            return self.resampler.process(input, out);
+        }
+        self.mapped.clear();
+        let mut frame = vec![0.0; self.to_channels];
+        for source in input.chunks_exact(self.from_channels) {
+            self.mapping.map_frame(source, &mut frame);
+            self.mapped.extend_from_slice(&frame);
+        }
+        self.resampler.process(&self.mapped, out);
+    }
+
+    fn flush(&mut self, out: &mut Vec<f32>) {
+        self.resampler.flush(out);
+    }
+
+    fn reset(&mut self) {
+        self.resampler.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wav::{WavFormat, WavWriter};
+
+    fn write_wav(name: &str, channels: u16, sample_rate: u32, samples: &[f32]) -> String {
+        let path = std::env::temp_dir().join(name);
+        let mut writer =
+            WavWriter::create(&path, channels, sample_rate, WavFormat::Float32).unwrap();
+        writer.write(samples).unwrap();
+        writer.finalize().unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn drain(queue: &PlaybackQueue, block: usize, limit: usize) -> (Vec<f32>, bool) {
+        let mut played = Vec::new();
+        let mut data = vec![0.0; block];
+        while played.len() < limit {
+            let ended = queue.fill(&mut data, false);
+            played.extend_from_slice(&data);
+            if ended {
+                return (played, true);
+            }
+        }
+        (played, false)
+    }
+
+    #[test]
+    fn test_converts_channels_and_rate() {
+        let path = write_wav(
+            "cpal-napi-player-convert.wav",
+            2,
+            24000,
+            &[0.5, 0.25].repeat(100),
+        );
+        let decoder = FileDecoder::open(&path).unwrap();
+        let player = FilePlayer::spawn(
+            decoder,
+            1,
+            48000,
+            ResampleQuality::Fast,
+            PlayFileOptions::default(),
+            StreamStatus::new(),
+        )
+        .unwrap();
+
+        let (played, ended) = drain(&player.queue(), 64, 10_000);
+        assert!(ended);
+        assert!(played.len() >= 198);
+        assert!(played[..198].iter().all(|s| (s - 0.375).abs() < 1e-6));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_loop_and_start_at() {
+        let samples: Vec<f32> = (0..100).map(|i| i as f32 / 100.0).collect();
+        let path = write_wav("cpal-napi-player-loop.wav", 1, 100, &samples);
+        let decoder = FileDecoder::open(&path).unwrap();
+        let options = PlayFileOptions {
+            looping: Some(true),
+            start_at: Some(0.5),
+            ..Default::default()
+        };
+        let player = FilePlayer::spawn(
+            decoder,
+            1,
+            100,
+            ResampleQuality::default(),
+            options,
+            StreamStatus::new(),
+        )
+        .unwrap();
+
+        let (played, ended) = drain(&player.queue(), 10, 200);
+        assert!(!ended);
+        assert_eq!(played[0], 0.5);
+        assert_eq!(played[50], 0.0);
+        assert_eq!(played[100], 0.5);
+        assert_eq!(played[150], 0.0);
+        drop(player);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_seek_after_end_restarts() {
+        let samples: Vec<f32> = (0..100).map(|i| i as f32 / 100.0).collect();
+        let path = write_wav("cpal-napi-player-seek.wav", 1, 100, &samples);
+        let decoder = FileDecoder::open(&path).unwrap();
+        let status = StreamStatus::new();
+        let player = FilePlayer::spawn(
+            decoder,
+            1,
+            100,
+            ResampleQuality::default(),
+            PlayFileOptions::default(),
+            status.clone(),
+        )
+        .unwrap();
+        let queue = player.queue();
+
+        let (_, ended) = drain(&queue, 10, 1000);
+        assert!(ended);
+        // The decode thread, not the callback, tells the stream.
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while !status.ended() && Instant::now() < deadline {
+            std::thread::sleep(FILL_INTERVAL);
+        }
+        assert!(status.ended());
+        queue.seek(0.9);
+        let (played, ended) = drain(&queue, 10, 1000);
+        assert!(ended);
+        assert_eq!(played[0], 0.9);
+        assert_eq!(played[9], 0.99);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
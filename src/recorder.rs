@@ -0,0 +1,198 @@
+use crate::callback::ChunkCallback;
+use crate::flac::FlacEncoder;
+use crate::ring::RingBuffer;
+use crate::wav::WavWriter;
+use napi::bindgen_prelude::Buffer;
+use napi::threadsafe_function::ThreadsafeFunctionCallMode;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+const DRAIN_INTERVAL: Duration = Duration::from_millis(10);
+const DRAIN_CHUNK: usize = 16384;
+
+/// Somewhere captured audio can be written off the realtime thread.
+pub(crate) trait SampleSink: Send + 'static {
+    fn write(&mut self, samples: &[f32]) -> io::Result<()>;
+    fn finalize(self: Box<Self>) -> io::Result<()>;
+}
+
+impl SampleSink for WavWriter {
+    fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        WavWriter::write(self, samples)
+    }
+
+    fn finalize(self: Box<Self>) -> io::Result<()> {
+        WavWriter::finalize(*self)
+    }
+}
+
+impl SampleSink for FlacEncoder<BufWriter<File>> {
+    fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        FlacEncoder::write(self, samples)
+    }
+
+    /// Also rewrites STREAMINFO so players know the length.
+    fn finalize(mut self: Box<Self>) -> io::Result<()> {
+        self.finish()?;
+        let streaminfo = self.streaminfo();
+        let file = self.get_mut();
+        file.seek(SeekFrom::Start(4))?;
+        file.write_all(&streaminfo)?;
+        file.flush()
+    }
+}
+
+impl SampleSink for FlacEncoder<ChunkWriter> {
+    fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        FlacEncoder::write(self, samples)
+    }
+
+    fn finalize(mut self: Box<Self>) -> io::Result<()> {
+        self.finish()
+    }
+}
+
+#[cfg(feature = "opus")]
+impl<W: Write + Send + 'static> SampleSink for crate::opus::OpusEncoder<W> {
+    fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        crate::opus::OpusEncoder::write(self, samples)
+    }
+
+    fn finalize(mut self: Box<Self>) -> io::Result<()> {
+        self.finish()
+    }
+}
+
+/// Collects encoded bytes and hands them to JavaScript as a `Buffer` on every flush.
+pub(crate) struct ChunkWriter {
+    callback: ChunkCallback,
+    pending: Vec<u8>,
+}
+
+impl ChunkWriter {
+    pub(crate) fn new(callback: ChunkCallback) -> Self {
+        ChunkWriter {
+            callback,
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.pending.is_empty() {
+            let chunk = Buffer::from(std::mem::take(&mut self.pending));
+            self.callback
+                .call(chunk, ThreadsafeFunctionCallMode::NonBlocking);
+        }
+        Ok(())
+    }
+}
+
+/// Moves samples from an input callback to a `SampleSink` on a writer thread.
+///
+/// The callback only pushes into a lock-free ring; the thread drains it every few
+/// milliseconds, so slow disks cost overruns instead of glitches.
+pub(crate) struct FileRecorder {
+    ring: Arc<RingBuffer>,
+    done: Arc<AtomicBool>,
+    handle: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl FileRecorder {
+    pub(crate) fn spawn(mut sink: Box<dyn SampleSink>, capacity: usize) -> Self {
+        let ring = Arc::new(RingBuffer::new(capacity));
+        let done = Arc::new(AtomicBool::new(false));
+        let (source, finished) = (ring.clone(), done.clone());
+        let handle = std::thread::spawn(move || {
+            let mut chunk = vec![0.0; DRAIN_CHUNK];
+            loop {
+                // Check before draining so nothing pushed before `finish` is missed.
+                let last = finished.load(Ordering::Acquire);
+                loop {
+                    let count = source.pop_slice(&mut chunk);
+                    if count == 0 {
+                        break;
+                    }
+                    sink.write(&chunk[..count])?;
+                }
+                if last {
+                    return sink.finalize();
+                }
+                std::thread::sleep(DRAIN_INTERVAL);
+            }
+        });
+        FileRecorder {
+            ring,
+            done,
+            handle: Some(handle),
+        }
+    }
+
+    /// The ring the input callback pushes into.
+    pub(crate) fn ring(&self) -> Arc<RingBuffer> {
+        self.ring.clone()
+    }
+
+    /// Writes what is left, finalizes the file and stops the thread.
+    pub(crate) fn finish(mut self) -> io::Result<()> {
+        self.stop()
+    }
+
+    fn stop(&mut self) -> io::Result<()> {
+        self.done.store(true, Ordering::Release);
+        match self.handle.take() {
+            Some(handle) => handle
+                .join()
+                .unwrap_or_else(|_| Err(io::Error::other("writer thread panicked"))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for FileRecorder {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    struct MemorySink(Arc<Mutex<(Vec<f32>, bool)>>);
+
+    impl SampleSink for MemorySink {
+        fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+            self.0.lock().unwrap().0.extend_from_slice(samples);
+            Ok(())
+        }
+
+        fn finalize(self: Box<Self>) -> io::Result<()> {
+            self.0.lock().unwrap().1 = true;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_finish_drains_and_finalizes() {
+        let shared = Arc::new(Mutex::new((Vec::new(), false)));
+        let recorder = FileRecorder::spawn(Box::new(MemorySink(shared.clone())), 64);
+        recorder.ring().push_slice(&[0.1, 0.2, 0.3]);
+        recorder.finish().unwrap();
+
+        let (samples, finalized) = &*shared.lock().unwrap();
+        assert_eq!(samples, &[0.1, 0.2, 0.3]);
+        assert!(finalized);
+    }
+}
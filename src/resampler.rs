@@ -0,0 +1,515 @@
+use crate::error::ErrorCode;
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+use std::sync::OnceLock;
+
+/// Kernel entries per zero crossing; values in between are interpolated linearly.
+const KERNEL_PHASES: usize = 512;
+/// Fraction of the lower Nyquist frequency kept, leaving room for the filter to roll off.
+const ROLLOFF: f64 = 0.95;
+
+/// Trade-off between CPU time and aliasing when converting sample rates.
+#[napi]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResampleQuality {
+    /// Linear interpolation; cheapest, but aliases audibly.
+    Fast,
+    /// Windowed sinc with 16 zero crossings, fine for realtime streams.
+    #[default]
+    Medium,
+    /// Windowed sinc with 64 zero crossings, for offline work.
+    High,
+}
+
+/// One side of a Kaiser-windowed sinc, sampled `KERNEL_PHASES` times per zero crossing.
+struct Kernel {
+    zero_crossings: usize,
+    table: Vec<f32>,
+}
+
+impl Kernel {
+    fn new(zero_crossings: usize, beta: f64) -> Self {
+        let len = zero_crossings * KERNEL_PHASES + 2;
+        let table = (0..len)
+            .map(|j| {
+                let x = j as f64 / KERNEL_PHASES as f64;
+                if x >= zero_crossings as f64 {
+                    return 0.0;
+                }
+                let sinc = if x == 0.0 {
+                    1.0
+                } else {
+                    (std::f64::consts::PI * x).sin() / (std::f64::consts::PI * x)
+                };
+                let r = x / zero_crossings as f64;
+                (sinc * bessel_i0(beta * (1.0 - r * r).sqrt()) / bessel_i0(beta)) as f32
+            })
+            .collect();
+        Kernel {
+            zero_crossings,
+            table,
+        }
+    }
+
+    fn for_quality(quality: ResampleQuality) -> Option<&'static Kernel> {
+        static MEDIUM: OnceLock<Kernel> = OnceLock::new();
+        static HIGH: OnceLock<Kernel> = OnceLock::new();
+        match quality {
+            ResampleQuality::Fast => None,
+            ResampleQuality::Medium => Some(MEDIUM.get_or_init(|| Kernel::new(16, 6.0))),
+            ResampleQuality::High => Some(HIGH.get_or_init(|| Kernel::new(64, 9.0))),
+        }
+    }
+
+    /// The kernel at `x` zero crossings from its centre.
+    fn at(&self, x: f64) -> f32 {
+        let position = x.abs() * KERNEL_PHASES as f64;
+        let index = position as usize;
+        if index + 1 >= self.table.len() {
+            return 0.0;
+        }
+        let fraction = (position - index as f64) as f32;
+        self.table[index] + (self.table[index + 1] - self.table[index]) * fraction
+    }
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let mut k = 1.0;
+    while term > sum * 1e-12 {
+        term *= (x / (2.0 * k)) * (x / (2.0 * k));
+        sum += term;
+        k += 1.0;
+    }
+    sum
+}
+
+/// Streaming sample-rate converter for interleaved `f32`.
+///
+/// State carries over between calls, so audio can be converted one block at a time
+/// without clicks at block boundaries. The sinc qualities hold back half their kernel
+/// as look-ahead; `flush` pushes it out at the end of a stream.
+pub(crate) struct Resampler {
+    channels: usize,
+    /// Input frames advanced per output frame, as the reduced ratio `from / to`.
+    from: u64,
+    to: u64,
+    /// Read position in `pending`: `frame + phase / to` frames, kept exact so long
+    /// streams do not drift.
+    frame: usize,
+    phase: u64,
+    pending: Vec<f32>,
+    /// Whether `pending` holds real input yet, rather than the history it starts with.
+    primed: bool,
+    kernel: Option<&'static Kernel>,
+    cutoff: f64,
+    /// Input frames the kernel reaches on each side of the read position.
+    reach: usize,
+}
+
+impl Resampler {
+    pub(crate) fn new(
+        channels: u16,
+        from_rate: u32,
+        to_rate: u32,
+        quality: ResampleQuality,
+    ) -> Self {
+        let channels = channels.max(1) as usize;
+        let (from, to) = (from_rate.max(1) as u64, to_rate.max(1) as u64);
+        let divisor = gcd(from, to);
+        let kernel = Kernel::for_quality(quality);
+        let cutoff = (to as f64 / from as f64).min(1.0) * ROLLOFF;
+        let reach = kernel.map_or(0, |k| (k.zero_crossings as f64 / cutoff).ceil() as usize);
+        Resampler {
+            channels,
+            from: from / divisor,
+            to: to / divisor,
+            // Start `reach` frames of history in, so the first output lines up with the first input.
+            frame: reach,
+            phase: 0,
+            pending: vec![0.0; reach * channels],
+            primed: false,
+            kernel,
+            cutoff,
+            reach,
+        }
+    }
+
+    /// Forgets the signal so far, e.g. after a seek.
+    pub(crate) fn reset(&mut self) {
+        self.pending.clear();
+        self.pending.resize(self.reach * self.channels, 0.0);
+        self.frame = self.reach;
+        self.phase = 0;
+        self.primed = false;
+    }
+
+    /// Makes room for `process` calls of up to `input_frames`, so they don't allocate.
+    fn reserve(&mut self, input_frames: usize) {
+        self.pending
+            .reserve((input_frames + 2 * self.reach + 2) * self.channels);
+    }
+
+    /// Most frames one `process` call of `input_frames` can produce.
+    fn max_output_frames(&self, input_frames: usize) -> usize {
+        ((input_frames + 2 * self.reach + 2) as u64 * self.to).div_ceil(self.from) as usize + 1
+    }
+
+    pub(crate) fn is_passthrough(&self) -> bool {
+        self.from == self.to
+    }
+
+    /// Input frames per output frame.
+    fn step(&self) -> f64 {
+        self.from as f64 / self.to as f64
+    }
+
+    fn position(&self) -> f64 {
+        self.frame as f64 + self.phase as f64 / self.to as f64
+    }
+
+    fn advance(&mut self) {
+        self.phase += self.from;
+        self.frame += (self.phase / self.to) as usize;
+        self.phase %= self.to;
+    }
+
+    /// Converts `input` and appends the result to `out`.
+    pub(crate) fn process(&mut self, input: &[f32], out: &mut Vec<f32>) {
+        if self.is_passthrough() {
+            out.extend_from_slice(input);
+            return;
+        }
+        if !self.primed && input.len() >= self.channels {
+            // Hold the first frame back through the history instead of ramping in from silence.
+            for history in self.pending.chunks_exact_mut(self.channels) {
+                history.copy_from_slice(&input[..self.channels]);
+            }
+            self.primed = true;
+        }
+        self.pending.extend_from_slice(input);
+        let available = self.pending.len() / self.channels;
+        let consumed = match self.kernel {
+            None => self.interpolate(available, out),
+            Some(kernel) => self.convolve(kernel, available, out),
+        };
+        let consumed = consumed.min(available);
+        self.pending.drain(..consumed * self.channels);
+        self.frame -= consumed;
+    }
+
+    /// Converts what is still held back, as if the input ended with silence.
+    pub(crate) fn flush(&mut self, out: &mut Vec<f32>) {
+        if self.is_passthrough() {
+            return;
+        }
+        let tail = match self.kernel {
+            // Repeat the last frame so the final position has a right-hand neighbour.
+            None => self.pending[self.pending.len().saturating_sub(self.channels)..].to_vec(),
+            Some(_) => vec![0.0; self.reach * self.channels],
+        };
+        self.process(&tail, out);
+    }
+
+    /// Returns how many frames of `pending` are no longer needed.
+    fn interpolate(&mut self, available: usize, out: &mut Vec<f32>) -> usize {
+        let channels = self.channels;
+        loop {
+            let index = self.frame;
+            if index + 1 >= available {
+                break;
+            }
+            let fraction = (self.phase as f64 / self.to as f64) as f32;
+            let current = &self.pending[index * channels..(index + 1) * channels];
+            let next = &self.pending[(index + 1) * channels..(index + 2) * channels];
+            out.extend(
+                current
+                    .iter()
+                    .zip(next)
+                    .map(|(a, b)| a + (b - a) * fraction),
+            );
+            self.advance();
+        }
+        self.frame
+    }
+
+    fn convolve(&mut self, kernel: &Kernel, available: usize, out: &mut Vec<f32>) -> usize {
+        let channels = self.channels;
+        let gain = self.cutoff as f32;
+        loop {
+            let centre = self.frame;
+            let position = self.position();
+            if centre + self.reach >= available {
+                break;
+            }
+            let first = (centre + 1).saturating_sub(self.reach);
+            let start = out.len();
+            out.resize(start + channels, 0.0);
+            for i in first..=centre + self.reach {
+                let weight = gain * kernel.at((position - i as f64) * self.cutoff);
+                let frame = &self.pending[i * channels..(i + 1) * channels];
+                for (acc, sample) in out[start..].iter_mut().zip(frame) {
+                    *acc += weight * sample;
+                }
+            }
+            self.advance();
+        }
+        (self.frame + 1).saturating_sub(self.reach)
+    }
+}
+
+/// Feeds an output callback at one rate from a source at another, pulling just enough
+/// input for each block.
+pub(crate) struct PullResampler {
+    resampler: Resampler,
+    input: Vec<f32>,
+    output: Vec<f32>,
+}
+
+impl PullResampler {
+    /// `max_frames` is the largest block `fill` is asked for.
+    pub(crate) fn new(
+        channels: u16,
+        from_rate: u32,
+        to_rate: u32,
+        quality: ResampleQuality,
+        max_frames: usize,
+    ) -> Self {
+        let mut resampler = Resampler::new(channels, from_rate, to_rate, quality);
+        let input_frames = (max_frames as f64 * resampler.step()).ceil() as usize + 1;
+        resampler.reserve(input_frames);
+        let output_frames = max_frames + 2 * resampler.max_output_frames(input_frames);
+        let channels = resampler.channels;
+        PullResampler {
+            resampler,
+            input: Vec::with_capacity(input_frames * channels),
+            output: Vec::with_capacity(output_frames * channels),
+        }
+    }
+
+    /// Fills `data`, asking `source` to fill input blocks at the source rate as needed.
+    pub(crate) fn fill(&mut self, data: &mut [f32], mut source: impl FnMut(&mut [f32])) {
+        let channels = self.resampler.channels;
+        while self.output.len() < data.len() {
+            let missing = (data.len() - self.output.len()).div_ceil(channels);
+            let frames = (missing as f64 * self.resampler.step()).ceil() as usize + 1;
+            self.input.resize(frames * channels, 0.0);
+            source(&mut self.input);
+            self.resampler.process(&self.input, &mut self.output);
+        }
+        data.copy_from_slice(&self.output[..data.len()]);
+        self.output.drain(..data.len());
+    }
+}
+
+/// Converts input callback blocks from the device rate, passing them through untouched
+/// when the rates already match.
+pub(crate) struct PushResampler {
+    resampler: Option<Resampler>,
+    output: Vec<f32>,
+}
+
+impl PushResampler {
+    /// `max_frames` is the largest block `process` is given.
+    pub(crate) fn new(
+        channels: u16,
+        from_rate: u32,
+        to_rate: u32,
+        quality: ResampleQuality,
+        max_frames: usize,
+    ) -> Self {
+        let resampler = (from_rate != to_rate).then(|| {
+            let mut resampler = Resampler::new(channels, from_rate, to_rate, quality);
+            resampler.reserve(max_frames);
+            resampler
+        });
+        let output = resampler.as_ref().map_or(0, |resampler| {
+            resampler.max_output_frames(max_frames) * resampler.channels
+        });
+        PushResampler {
+            resampler,
+            output: Vec::with_capacity(output),
+        }
+    }
+
+    pub(crate) fn process<'a>(&'a mut self, data: &'a [f32]) -> &'a [f32] {
+        match &mut self.resampler {
+            None => data,
+            Some(resampler) => {
+                self.output.clear();
+                resampler.process(data, &mut self.output);
+                &self.output
+            }
+        }
+    }
+}
+
+/// Converts interleaved samples from one sample rate to another.
+#[napi]
+pub fn resample(
+    data: Float32Array,
+    from: u32,
+    to: u32,
+    channels: Option<u16>,
+    quality: Option<ResampleQuality>,
+) -> Result<Float32Array, ErrorCode> {
+    let channels = channels.unwrap_or(1);
+    if from == 0 || to == 0 || channels == 0 {
+        return Err(Error::new(
+            ErrorCode::InvalidArgument,
+            "Sample rates and channel count must be positive",
+        ));
+    }
+    if !data.len().is_multiple_of(channels as usize) {
+        return Err(Error::new(
+            ErrorCode::InvalidArgument,
+            format!(
+                "{} samples do not divide into {} channels",
+                data.len(),
+                channels
+            ),
+        ));
+    }
+    let mut resampler =
+        Resampler::new(channels, from, to, quality.unwrap_or(ResampleQuality::High));
+    let mut out = Vec::with_capacity((data.len() as f64 * to as f64 / from as f64) as usize + 16);
+    resampler.process(&data, &mut out);
+    resampler.flush(&mut out);
+    Ok(Float32Array::new(out))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(frequency: f64, rate: u32, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .map(|i| (std::f64::consts::TAU * frequency * i as f64 / rate as f64).sin() as f32)
+            .collect()
+    }
+
+    fn convert(input: &[f32], from: u32, to: u32, quality: ResampleQuality) -> Vec<f32> {
+        let mut resampler = Resampler::new(1, from, to, quality);
+        let mut out = Vec::new();
+        for block in input.chunks(100) {
+            resampler.process(block, &mut out);
+        }
+        resampler.flush(&mut out);
+        out
+    }
+
+    #[test]
+    fn test_linear_upsamples_by_interpolating() {
+        let mut resampler = Resampler::new(1, 1, 2, ResampleQuality::Fast);
+        let mut out = Vec::new();
+        resampler.process(&[0.0, 1.0], &mut out);
+        resampler.process(&[0.0], &mut out);
+        assert_eq!(out, vec![0.0, 0.5, 1.0, 0.5]);
+    }
+
+    #[test]
+    fn test_linear_downsamples_across_calls() {
+        let mut resampler = Resampler::new(2, 48000, 24000, ResampleQuality::Fast);
+        let mut out = Vec::new();
+        for frame in 0..8 {
+            let value = frame as f32;
+            resampler.process(&[value, -value], &mut out);
+        }
+        assert_eq!(out, vec![0.0, 0.0, 2.0, -2.0, 4.0, -4.0, 6.0, -6.0]);
+    }
+
+    #[test]
+    fn test_output_length_matches_ratio() {
+        for quality in [
+            ResampleQuality::Fast,
+            ResampleQuality::Medium,
+            ResampleQuality::High,
+        ] {
+            assert_eq!(convert(&[0.5; 4800], 48000, 44100, quality).len(), 4410);
+            assert_eq!(convert(&[0.5; 441], 44100, 48000, quality).len(), 480);
+        }
+    }
+
+    #[test]
+    fn test_sinc_keeps_passband_and_timing() {
+        let input = sine(1000.0, 48000, 4800);
+        let out = convert(&input, 48000, 44100, ResampleQuality::Medium);
+        let expected = sine(1000.0, 44100, out.len());
+        // Skip the edges, where the kernel runs past the ends of the input.
+        let error = out[200..4200]
+            .iter()
+            .zip(&expected[200..4200])
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f32::max);
+        assert!(error < 0.01, "max error {}", error);
+    }
+
+    #[test]
+    fn test_sinc_starts_without_ramp() {
+        let out = convert(&[0.5; 200], 24000, 48000, ResampleQuality::Medium);
+        assert!(out[..300].iter().all(|s| (s - 0.5).abs() < 0.01));
+        // The end decays into silence once flushed.
+        assert!(out[399].abs() < 0.3);
+    }
+
+    #[test]
+    fn test_sinc_rejects_aliases() {
+        // 30 kHz cannot be represented at 44.1 kHz and must be filtered out, not folded down.
+        let input = sine(30000.0, 96000, 9600);
+        let out = convert(&input, 96000, 44100, ResampleQuality::High);
+        let peak = out[500..4000].iter().map(|s| s.abs()).fold(0.0, f32::max);
+        assert!(peak < 0.01, "alias peak {}", peak);
+    }
+
+    #[test]
+    fn test_pull_resampler_fills_blocks() {
+        let mut pull = PullResampler::new(2, 44100, 48000, ResampleQuality::Medium, 128);
+        let mut pulled = 0;
+        let mut data = [1.0; 256];
+        for _ in 0..10 {
+            pull.fill(&mut data, |input| {
+                pulled += input.len();
+                input.fill(0.25);
+            });
+        }
+        assert!((data[255] - 0.25).abs() < 1e-3);
+        // 1280 output frames need about 1176 input frames, plus the kernel's look-ahead.
+        assert!(pulled / 2 < 1176 + 64, "pulled {} frames", pulled / 2);
+    }
+
+    #[test]
+    fn test_bridges_stay_within_reserved_capacity() {
+        for (from, to) in [(44100, 48000), (48000, 44100), (22050, 48000)] {
+            let mut pull = PullResampler::new(2, from, to, ResampleQuality::Medium, 300);
+            let mut push = PushResampler::new(2, from, to, ResampleQuality::Medium, 300);
+            let capacities = |pull: &PullResampler, push: &PushResampler| {
+                let inner = push.resampler.as_ref().unwrap();
+                [
+                    pull.input.capacity(),
+                    pull.output.capacity(),
+                    pull.resampler.pending.capacity(),
+                    push.output.capacity(),
+                    inner.pending.capacity(),
+                ]
+            };
+            let reserved = capacities(&pull, &push);
+            for frames in [300, 1, 299, 300, 17, 300] {
+                let mut data = vec![0.5; frames * 2];
+                for _ in 0..5 {
+                    pull.fill(&mut data, |input| input.fill(0.25));
+                    push.process(&data);
+                }
+            }
+            assert_eq!(capacities(&pull, &push), reserved, "{} -> {}", from, to);
+        }
+    }
+}
@@ -0,0 +1,297 @@
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+/// Fixed-capacity lock-free ring of `f32` samples.
+///
+/// Meant for one producer and one consumer. Samples are stored as `AtomicU32`
+/// bit patterns so no access is ever a data race, and the read position is
+/// advanced with a CAS so JS-side reads or `clear()` can safely overlap with a
+/// stream draining the same ring.
+pub(crate) struct RingBuffer {
+    slots: Box<[AtomicU32]>,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    overruns: AtomicU64,
+    underruns: AtomicU64,
+}
+
+impl RingBuffer {
+    pub(crate) fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        RingBuffer {
+            slots: (0..capacity).map(|_| AtomicU32::new(0)).collect(),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            overruns: AtomicU64::new(0),
+            underruns: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        // Head first: a tail read later is never behind it, so the difference can't wrap.
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+        tail.wrapping_sub(head).min(self.capacity())
+    }
+
+    pub(crate) fn overruns(&self) -> u64 {
+        self.overruns.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn underruns(&self) -> u64 {
+        self.underruns.load(Ordering::Relaxed)
+    }
+
+    /// Appends as many samples as fit and returns that count; the rest are counted as overruns.
+    pub(crate) fn push_slice(&self, data: &[f32]) -> usize {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Acquire);
+        let free = self.capacity() - tail.wrapping_sub(head).min(self.capacity());
+        let count = data.len().min(free);
+
+        for (i, sample) in data[..count].iter().enumerate() {
+            self.slot(tail.wrapping_add(i))
+                .store(sample.to_bits(), Ordering::Relaxed);
+        }
+        self.tail.store(tail.wrapping_add(count), Ordering::Release);
+
+        if count < data.len() {
+            self.overruns
+                .fetch_add((data.len() - count) as u64, Ordering::Relaxed);
+        }
+        count
+    }
+
+    /// Removes up to `out.len()` samples into `out` and returns how many were read.
+    pub(crate) fn pop_slice(&self, out: &mut [f32]) -> usize {
+        loop {
+            let head = self.head.load(Ordering::Acquire);
+            let count = self.copy_from(head, out);
+            if self
+                .head
+                .compare_exchange(
+                    head,
+                    head.wrapping_add(count),
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                )
+                .is_ok()
+            {
+                return count;
+            }
+        }
+    }
+
+    /// Like [`pop_slice`](Self::pop_slice), but pads the remainder with silence and counts it
+    /// as an underrun.
+    pub(crate) fn pop_or_silence(&self, out: &mut [f32]) -> usize {
+        let count = self.pop_slice(out);
+        if count < out.len() {
+            out[count..].fill(0.0);
+            self.underruns
+                .fetch_add((out.len() - count) as u64, Ordering::Relaxed);
+        }
+        count
+    }
+
+    /// Drops up to `count` of the oldest samples and returns how many were dropped.
+    pub(crate) fn discard(&self, count: usize) -> usize {
+        loop {
+            let head = self.head.load(Ordering::Acquire);
+            let tail = self.tail.load(Ordering::Acquire);
+            let count = tail.wrapping_sub(head).min(self.capacity()).min(count);
+            if self
+                .head
+                .compare_exchange(
+                    head,
+                    head.wrapping_add(count),
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                )
+                .is_ok()
+            {
+                return count;
+            }
+        }
+    }
+
+    /// Copies up to `out.len()` samples without consuming them.
+    pub(crate) fn peek_slice(&self, out: &mut [f32]) -> usize {
+        self.copy_from(self.head.load(Ordering::Acquire), out)
+    }
+
+    pub(crate) fn clear(&self) {
+        let mut head = self.head.load(Ordering::Acquire);
+        loop {
+            // Read the tail after the head so it is never behind the position we replace.
+            let tail = self.tail.load(Ordering::Acquire);
+            match self
+                .head
+                .compare_exchange(head, tail, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => return,
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    fn copy_from(&self, head: usize, out: &mut [f32]) -> usize {
+        let tail = self.tail.load(Ordering::Acquire);
+        let count = tail.wrapping_sub(head).min(self.capacity()).min(out.len());
+        for (i, sample) in out[..count].iter_mut().enumerate() {
+            *sample = f32::from_bits(self.slot(head.wrapping_add(i)).load(Ordering::Relaxed));
+        }
+        count
+    }
+
+    fn slot(&self, position: usize) -> &AtomicU32 {
+        &self.slots[position % self.capacity()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_and_pop() {
+        let ring = RingBuffer::new(4);
+        assert_eq!(ring.push_slice(&[1.0, 2.0, 3.0]), 3);
+
+        let mut out = [0.0; 2];
+        assert_eq!(ring.pop_slice(&mut out), 2);
+        assert_eq!(out, [1.0, 2.0]);
+        assert_eq!(ring.len(), 1);
+    }
+
+    #[test]
+    fn test_wraps_around() {
+        let ring = RingBuffer::new(3);
+        let mut out = [0.0; 2];
+        for round in 0..5 {
+            let base = round as f32 * 2.0;
+            assert_eq!(ring.push_slice(&[base, base + 1.0]), 2);
+            assert_eq!(ring.pop_slice(&mut out), 2);
+            assert_eq!(out, [base, base + 1.0]);
+        }
+    }
+
+    #[test]
+    fn test_overrun_is_counted() {
+        let ring = RingBuffer::new(2);
+        assert_eq!(ring.push_slice(&[1.0, 2.0, 3.0]), 2);
+        assert_eq!(ring.overruns(), 1);
+        assert_eq!(ring.len(), 2);
+    }
+
+    #[test]
+    fn test_underrun_pads_with_silence() {
+        let ring = RingBuffer::new(4);
+        ring.push_slice(&[0.5]);
+
+        let mut out = [1.0; 3];
+        assert_eq!(ring.pop_or_silence(&mut out), 1);
+        assert_eq!(out, [0.5, 0.0, 0.0]);
+        assert_eq!(ring.underruns(), 2);
+    }
+
+    #[test]
+    fn test_peek_and_clear() {
+        let ring = RingBuffer::new(4);
+        ring.push_slice(&[1.0, 2.0]);
+
+        let mut out = [0.0; 4];
+        assert_eq!(ring.peek_slice(&mut out), 2);
+        assert_eq!(ring.len(), 2);
+
+        ring.clear();
+        assert_eq!(ring.len(), 0);
+    }
+
+    #[test]
+    fn test_discard_drops_oldest() {
+        let ring = RingBuffer::new(4);
+        ring.push_slice(&[1.0, 2.0, 3.0]);
+        assert_eq!(ring.discard(2), 2);
+        assert_eq!(ring.discard(5), 1);
+        assert_eq!(ring.len(), 0);
+    }
+
+    #[test]
+    fn test_concurrent_producer_and_consumer() {
+        let ring = std::sync::Arc::new(RingBuffer::new(64));
+        let producer = {
+            let ring = ring.clone();
+            std::thread::spawn(move || {
+                let mut next = 0.0;
+                while next < 10_000.0 {
+                    if ring.push_slice(&[next]) == 1 {
+                        next += 1.0;
+                    }
+                }
+            })
+        };
+
+        let mut expected = 0.0;
+        let mut out = [0.0; 16];
+        while expected < 10_000.0 {
+            let count = ring.pop_slice(&mut out);
+            for sample in &out[..count] {
+                assert_eq!(*sample, expected);
+                expected += 1.0;
+            }
+        }
+        producer.join().unwrap();
+    }
+
+    #[test]
+    fn test_len_never_exceeds_what_is_queued() {
+        use std::sync::atomic::AtomicBool;
+        use std::sync::Arc;
+
+        let ring = Arc::new(RingBuffer::new(64));
+        let written = Arc::new(AtomicUsize::new(0));
+        let read = Arc::new(AtomicUsize::new(0));
+        let done = Arc::new(AtomicBool::new(false));
+        let producer = {
+            let (ring, written, done) = (ring.clone(), written.clone(), done.clone());
+            std::thread::spawn(move || {
+                for _ in 0..200_000 {
+                    // Count before pushing so `written` never lags the tail.
+                    written.fetch_add(8, Ordering::SeqCst);
+                    let pushed = ring.push_slice(&[0.0; 8]);
+                    written.fetch_sub(8 - pushed, Ordering::SeqCst);
+                }
+                done.store(true, Ordering::SeqCst);
+            })
+        };
+        let consumer = {
+            let (ring, read, done) = (ring.clone(), read.clone(), done.clone());
+            std::thread::spawn(move || {
+                let mut out = [0.0; 5];
+                while !done.load(Ordering::SeqCst) || ring.len() > 0 {
+                    // Count after popping so `read` never runs ahead of the head.
+                    let count = ring.pop_slice(&mut out);
+                    read.fetch_add(count, Ordering::SeqCst);
+                }
+            })
+        };
+
+        while !done.load(Ordering::SeqCst) {
+            let before = read.load(Ordering::SeqCst);
+            let len = ring.len();
+            let after = written.load(Ordering::SeqCst);
+            assert!(
+                len <= after - before,
+                "len {} with {} queued",
+                len,
+                after - before
+            );
+        }
+        producer.join().unwrap();
+        consumer.join().unwrap();
+    }
+}
@@ -0,0 +1,379 @@
+use crate::stream::StreamStatus;
+use cpal::traits::DeviceTrait;
+use cpal::{FromSample, Sample, SizedSample, I24, U24};
+use std::sync::Arc;
+use std::time::Duration;
+
+const SCRATCH_CAPACITY: usize = 8192;
+
+/// Evaluates `$body` with `$T` naming the sample type of `$format`, or `$other` for
+/// formats cpal added after this list.
+macro_rules! with_sample_type {
+    ($format:expr, $T:ident => $body:expr, _ => $other:expr) => {
+        match $format {
+            cpal::SampleFormat::F32 => {
+                type $T = f32;
+                $body
+            }
+            cpal::SampleFormat::F64 => {
+                type $T = f64;
+                $body
+            }
+            cpal::SampleFormat::I8 => {
+                type $T = i8;
+                $body
+            }
+            cpal::SampleFormat::I16 => {
+                type $T = i16;
+                $body
+            }
+            cpal::SampleFormat::I24 => {
+                type $T = I24;
+                $body
+            }
+            cpal::SampleFormat::I32 => {
+                type $T = i32;
+                $body
+            }
+            cpal::SampleFormat::I64 => {
+                type $T = i64;
+                $body
+            }
+            cpal::SampleFormat::U8 => {
+                type $T = u8;
+                $body
+            }
+            cpal::SampleFormat::U16 => {
+                type $T = u16;
+                $body
+            }
+            cpal::SampleFormat::U24 => {
+                type $T = U24;
+                $body
+            }
+            cpal::SampleFormat::U32 => {
+                type $T = u32;
+                $body
+            }
+            cpal::SampleFormat::U64 => {
+                type $T = u64;
+                $body
+            }
+            _ => $other,
+        }
+    };
+}
+
+/// Builds an output stream in `sample_format`, rendering through an `f32` callback.
+///
+/// Device buffers are rendered in calls of at most `max_chunk_frames`, and `timing`
+/// records each device callback once, however many calls it takes to fill.
+pub(crate) fn build_output_stream<D, E>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    sample_format: cpal::SampleFormat,
+    timing: Option<Arc<StreamStatus>>,
+    mut data_callback: D,
+    error_callback: E,
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    D: FnMut(&mut [f32], &cpal::OutputCallbackInfo) + Send + 'static,
+    E: FnMut(cpal::StreamError) + Send + 'static,
+{
+    let (channels, sample_rate) = (config.channels.max(1) as usize, config.sample_rate);
+    let chunk_frames = max_chunk_frames(config.channels);
+    if sample_format == cpal::SampleFormat::F32 {
+        return device.build_output_stream(
+            config,
+            move |data: &mut [f32], info: &cpal::OutputCallbackInfo| {
+                if let Some(timing) = &timing {
+                    timing.record_output(info, data.len() / channels);
+                }
+                for (i, chunk) in data.chunks_mut(chunk_frames * channels).enumerate() {
+                    let offset = chunk_offset(i * chunk_frames, sample_rate);
+                    data_callback(chunk, &output_chunk_info(info, offset));
+                }
+            },
+            error_callback,
+            None,
+        );
+    }
+    with_sample_type!(
+        sample_format,
+        T => build_converted_output::<T, _, _>(device, config, timing, data_callback, error_callback),
+        _ => Err(cpal::BuildStreamError::StreamConfigNotSupported)
+    )
+}
+
+/// Builds an input stream in `sample_format`, delivering samples to an `f32` callback.
+///
+/// Device buffers are delivered in calls of at most `max_chunk_frames`, and `timing`
+/// records each device callback once, however many calls it is delivered in.
+pub(crate) fn build_input_stream<D, E>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    sample_format: cpal::SampleFormat,
+    timing: Option<Arc<StreamStatus>>,
+    mut data_callback: D,
+    error_callback: E,
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    D: FnMut(&[f32], &cpal::InputCallbackInfo) + Send + 'static,
+    E: FnMut(cpal::StreamError) + Send + 'static,
+{
+    let (channels, sample_rate) = (config.channels.max(1) as usize, config.sample_rate);
+    let chunk_frames = max_chunk_frames(config.channels);
+    if sample_format == cpal::SampleFormat::F32 {
+        return device.build_input_stream(
+            config,
+            move |data: &[f32], info: &cpal::InputCallbackInfo| {
+                if let Some(timing) = &timing {
+                    timing.record_input(info, data.len() / channels);
+                }
+                for (i, chunk) in data.chunks(chunk_frames * channels).enumerate() {
+                    let offset = chunk_offset(i * chunk_frames, sample_rate);
+                    data_callback(chunk, &input_chunk_info(info, offset));
+                }
+            },
+            error_callback,
+            None,
+        );
+    }
+    with_sample_type!(
+        sample_format,
+        T => build_converted_input::<T, _, _>(device, config, timing, data_callback, error_callback),
+        _ => Err(cpal::BuildStreamError::StreamConfigNotSupported)
+    )
+}
+
+/// Most frames a data callback is handed at once, so buffers sized for it when the
+/// stream is built never have to grow on the audio thread.
+pub(crate) fn max_chunk_frames(channels: u16) -> usize {
+    (SCRATCH_CAPACITY / channels.max(1) as usize).max(1)
+}
+
+/// Scratch space for one chunk, in whole frames.
+fn scratch_for(config: &cpal::StreamConfig) -> Vec<f32> {
+    vec![0.0; max_chunk_frames(config.channels) * config.channels.max(1) as usize]
+}
+
+/// How far into a device callback the chunk starting `frames` in lies.
+fn chunk_offset(frames: usize, sample_rate: u32) -> Duration {
+    Duration::from_nanos(frames as u64 * 1_000_000_000 / sample_rate.max(1) as u64)
+}
+
+/// `info` moved `offset` later, as if its chunk had a callback of its own.
+fn output_chunk_info(
+    info: &cpal::OutputCallbackInfo,
+    offset: Duration,
+) -> cpal::OutputCallbackInfo {
+    let ts = info.timestamp();
+    cpal::OutputCallbackInfo::new(cpal::OutputStreamTimestamp {
+        callback: ts.callback.add(offset).unwrap_or(ts.callback),
+        playback: ts.playback.add(offset).unwrap_or(ts.playback),
+    })
+}
+
+/// `info` moved `offset` later, as if its chunk had a callback of its own.
+fn input_chunk_info(info: &cpal::InputCallbackInfo, offset: Duration) -> cpal::InputCallbackInfo {
+    let ts = info.timestamp();
+    cpal::InputCallbackInfo::new(cpal::InputStreamTimestamp {
+        callback: ts.callback.add(offset).unwrap_or(ts.callback),
+        capture: ts.capture.add(offset).unwrap_or(ts.capture),
+    })
+}
+
+fn build_converted_output<T, D, E>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    timing: Option<Arc<StreamStatus>>,
+    mut data_callback: D,
+    error_callback: E,
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    T: SizedSample + FromSample<f32>,
+    D: FnMut(&mut [f32], &cpal::OutputCallbackInfo) + Send + 'static,
+    E: FnMut(cpal::StreamError) + Send + 'static,
+{
+    let (channels, sample_rate) = (config.channels.max(1) as usize, config.sample_rate);
+    let mut scratch = scratch_for(config);
+    device.build_output_stream(
+        config,
+        move |data: &mut [T], info: &cpal::OutputCallbackInfo| {
+            if let Some(timing) = &timing {
+                timing.record_output(info, data.len() / channels);
+            }
+            let chunk_frames = scratch.len() / channels;
+            for (i, chunk) in data.chunks_mut(scratch.len()).enumerate() {
+                let converted = &mut scratch[..chunk.len()];
+                let offset = chunk_offset(i * chunk_frames, sample_rate);
+                data_callback(converted, &output_chunk_info(info, offset));
+                write_samples(converted, chunk);
+            }
+        },
+        error_callback,
+        None,
+    )
+}
+
+fn build_converted_input<T, D, E>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    timing: Option<Arc<StreamStatus>>,
+    mut data_callback: D,
+    error_callback: E,
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+    D: FnMut(&[f32], &cpal::InputCallbackInfo) + Send + 'static,
+    E: FnMut(cpal::StreamError) + Send + 'static,
+{
+    let (channels, sample_rate) = (config.channels.max(1) as usize, config.sample_rate);
+    let mut scratch = scratch_for(config);
+    device.build_input_stream(
+        config,
+        move |data: &[T], info: &cpal::InputCallbackInfo| {
+            if let Some(timing) = &timing {
+                timing.record_input(info, data.len() / channels);
+            }
+            let chunk_frames = scratch.len() / channels;
+            for (i, chunk) in data.chunks(scratch.len()).enumerate() {
+                let converted = &mut scratch[..chunk.len()];
+                read_samples(chunk, converted);
+                let offset = chunk_offset(i * chunk_frames, sample_rate);
+                data_callback(converted, &input_chunk_info(info, offset));
+            }
+        },
+        error_callback,
+        None,
+    )
+}
+
+/// Converts `src` into the samples of `data`, whatever its format.
+pub(crate) fn write_data(src: &[f32], data: &mut cpal::Data) {
+    with_sample_type!(data.sample_format(), T => write_as::<T>(src, data), _ => {})
+}
+
+fn write_as<T: SizedSample + FromSample<f32>>(src: &[f32], data: &mut cpal::Data) {
+    if let Some(out) = data.as_slice_mut::<T>() {
+        write_samples(src, out);
+    }
+}
+
+/// Converts the samples of `data`, whatever its format, into `dst`.
+pub(crate) fn read_data(data: &cpal::Data, dst: &mut [f32]) {
+    with_sample_type!(data.sample_format(), T => read_as::<T>(data, dst), _ => {})
+}
+
+fn read_as<T: SizedSample>(data: &cpal::Data, dst: &mut [f32])
+where
+    f32: FromSample<T>,
+{
+    if let Some(src) = data.as_slice::<T>() {
+        read_samples(src, dst);
+    }
+}
+
+pub(crate) fn write_samples<T: Sample + FromSample<f32>>(src: &[f32], dst: &mut [T]) {
+    for (out, sample) in dst.iter_mut().zip(src) {
+        *out = T::from_sample(sample.clamp(-1.0, 1.0));
+    }
+}
+
+pub(crate) fn read_samples<T: Sample>(src: &[T], dst: &mut [f32])
+where
+    f32: FromSample<T>,
+{
+    for (out, sample) in dst.iter_mut().zip(src) {
+        *out = sample.to_sample::<f32>();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::null_host::{create_null_host, NullDeviceOptions, NullHostOptions};
+    use crate::stream::AudioStream;
+    use crate::types::SampleFormat;
+    use cpal::traits::HostTrait;
+    use std::sync::Mutex;
+    use std::time::Instant;
+
+    #[test]
+    fn test_chunks_share_one_recorded_block() {
+        let device = create_null_host(Some(NullHostOptions {
+            devices: Some(vec![NullDeviceOptions {
+                sample_formats: Some(vec![SampleFormat::I16]),
+                block_frames: Some(8192),
+                ..Default::default()
+            }]),
+            speed: Some(50.0),
+            ..Default::default()
+        }))
+        .inner
+        .devices()
+        .unwrap()
+        .next()
+        .unwrap();
+        let config = device.default_output_config().unwrap().config();
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let sink = calls.clone();
+        let status = StreamStatus::new();
+        let stream = build_output_stream(
+            &device,
+            &config,
+            cpal::SampleFormat::I16,
+            Some(status.clone()),
+            move |data: &mut [f32], info: &cpal::OutputCallbackInfo| {
+                sink.lock()
+                    .unwrap()
+                    .push((data.len(), info.timestamp().playback));
+            },
+            |_| {},
+        )
+        .unwrap();
+        let mut stream = AudioStream::new(stream, status);
+        stream.play().unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while calls.lock().unwrap().len() < 2 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        stream.close().unwrap();
+
+        let calls = calls.lock().unwrap();
+        assert!(calls.len() >= 2, "only {} chunks rendered", calls.len());
+        // A stereo block of 8192 frames is rendered as two chunks of 4096 frames.
+        assert_eq!((calls[0].0, calls[1].0), (8192, 8192));
+        let step = calls[1].1.duration_since(&calls[0].1).unwrap();
+        assert_eq!(step, chunk_offset(4096, config.sample_rate));
+        let block = stream.last_block().unwrap();
+        assert_eq!(block.frames, 8192);
+        assert_eq!(block.index as usize, calls.len() / 2);
+    }
+
+    #[test]
+    fn test_write_samples_i16() {
+        let mut out = [0i16; 3];
+        write_samples(&[-1.0, 0.0, 2.0], &mut out);
+        assert_eq!(out, [i16::MIN, 0, i16::MAX]);
+    }
+
+    #[test]
+    fn test_write_samples_u16_is_offset() {
+        let mut out = [0u16; 1];
+        write_samples(&[0.0], &mut out);
+        assert_eq!(out, [32768]);
+    }
+
+    #[test]
+    fn test_round_trip_i24() {
+        let mut encoded = [I24::EQUILIBRIUM; 2];
+        write_samples(&[0.5, -0.5], &mut encoded);
+
+        let mut decoded = [0.0f32; 2];
+        read_samples(&encoded, &mut decoded);
+        assert!((decoded[0] - 0.5).abs() < 1e-6);
+        assert!((decoded[1] + 0.5).abs() < 1e-6);
+    }
+}
@@ -1,67 +1,548 @@
+use crate::callback::{EndedCallback, StreamErrorCallback};
+use crate::error::{cpal_error, ErrorCode, StreamError, StreamErrorEvent};
+use crate::player::PlaybackQueue;
 use cpal::traits::StreamTrait;
 use napi::bindgen_prelude::*;
+use napi::threadsafe_function::ThreadsafeFunctionCallMode;
 use napi_derive::napi;
+use std::sync::atomic::{fence, AtomicBool, AtomicI64, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 
 #[napi(object)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub struct StreamInstant {
     pub seconds: i64,
     pub nanos: u32,
 }
 
 #[napi(object)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub struct InputStreamTimestamp {
     pub callback: StreamInstant,
     pub capture: StreamInstant,
 }
 
 #[napi(object)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub struct OutputStreamTimestamp {
     pub callback: StreamInstant,
     pub playback: StreamInstant,
 }
 
 #[napi(object)]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
 pub struct InputCallbackInfo {
     pub timestamp: InputStreamTimestamp,
+    /// Seconds between the hardware capturing the block and the callback running.
+    pub latency: f64,
 }
 
 #[napi(object)]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
 pub struct OutputCallbackInfo {
     pub timestamp: OutputStreamTimestamp,
+    /// Seconds between the callback running and the block reaching the hardware.
+    pub latency: f64,
+}
+
+/// Timing of the most recent block a stream processed.
+#[napi(object)]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BlockTimestamp {
+    /// Number of blocks processed so far, including this one.
+    pub index: i64,
+    pub frames: u32,
+    pub callback: StreamInstant,
+    /// When the block was captured (input) or will be played (output).
+    pub device: StreamInstant,
+    /// Seconds between `device` and `callback`.
+    pub latency: f64,
+}
+
+impl StreamInstant {
+    pub(crate) fn as_nanos(&self) -> i64 {
+        self.seconds * 1_000_000_000 + self.nanos as i64
+    }
+
+    pub(crate) fn from_nanos(nanos: i64) -> Self {
+        StreamInstant {
+            seconds: nanos.div_euclid(1_000_000_000),
+            nanos: nanos.rem_euclid(1_000_000_000) as u32,
+        }
+    }
+}
+
+fn latency(earlier: StreamInstant, later: StreamInstant) -> f64 {
+    (later.as_nanos() - earlier.as_nanos()) as f64 / 1e9
+}
+
+impl From<cpal::StreamInstant> for StreamInstant {
+    fn from(instant: cpal::StreamInstant) -> Self {
+        // cpal keeps the fields private, so measure the offset from its zero instant.
+        let zero = cpal::StreamInstant::new(0, 0);
+        if let Some(d) = instant.duration_since(&zero) {
+            return StreamInstant {
+                seconds: d.as_secs() as i64,
+                nanos: d.subsec_nanos(),
+            };
+        }
+        let d = zero.duration_since(&instant).unwrap_or_default();
+        if d.subsec_nanos() == 0 {
+            StreamInstant {
+                seconds: -(d.as_secs() as i64),
+                nanos: 0,
+            }
+        } else {
+            StreamInstant {
+                seconds: -(d.as_secs() as i64) - 1,
+                nanos: 1_000_000_000 - d.subsec_nanos(),
+            }
+        }
+    }
+}
+
+impl From<&cpal::InputCallbackInfo> for InputCallbackInfo {
+    fn from(info: &cpal::InputCallbackInfo) -> Self {
+        let ts = info.timestamp();
+        let timestamp = InputStreamTimestamp {
+            callback: ts.callback.into(),
+            capture: ts.capture.into(),
+        };
+        InputCallbackInfo {
+            timestamp,
+            latency: latency(timestamp.capture, timestamp.callback),
+        }
+    }
+}
+
+impl From<&cpal::OutputCallbackInfo> for OutputCallbackInfo {
+    fn from(info: &cpal::OutputCallbackInfo) -> Self {
+        let ts = info.timestamp();
+        let timestamp = OutputStreamTimestamp {
+            callback: ts.callback.into(),
+            playback: ts.playback.into(),
+        };
+        OutputCallbackInfo {
+            timestamp,
+            latency: latency(timestamp.callback, timestamp.playback),
+        }
+    }
+}
+
+/// Timing of the latest block, written by the audio thread without locking.
+///
+/// A sequence lock: `sequence` is odd while a write is in progress and readers retry
+/// until they see the same even value before and after copying the fields.
+#[derive(Default)]
+struct BlockClock {
+    sequence: AtomicU64,
+    frames: AtomicU32,
+    callback: AtomicI64,
+    device: AtomicI64,
+}
+
+impl BlockClock {
+    fn store(&self, callback: StreamInstant, device: StreamInstant, frames: usize) {
+        self.sequence.fetch_add(1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        self.frames.store(frames as u32, Ordering::Relaxed);
+        self.callback.store(callback.as_nanos(), Ordering::Relaxed);
+        self.device.store(device.as_nanos(), Ordering::Relaxed);
+        self.sequence.fetch_add(1, Ordering::Release);
+    }
+
+    fn load(&self) -> Option<BlockTimestamp> {
+        loop {
+            let before = self.sequence.load(Ordering::Acquire);
+            if before % 2 == 1 {
+                std::hint::spin_loop();
+                continue;
+            }
+            let frames = self.frames.load(Ordering::Relaxed);
+            let callback = StreamInstant::from_nanos(self.callback.load(Ordering::Relaxed));
+            let device = StreamInstant::from_nanos(self.device.load(Ordering::Relaxed));
+            fence(Ordering::Acquire);
+            if self.sequence.load(Ordering::Relaxed) != before {
+                continue;
+            }
+            if before == 0 {
+                return None;
+            }
+            return Some(BlockTimestamp {
+                index: (before / 2) as i64,
+                frames,
+                callback,
+                device,
+                latency: latency(callback, device).abs(),
+            });
+        }
+    }
+}
+
+/// State shared between an `AudioStream` and the cpal callbacks of its stream.
+pub(crate) struct StreamStatus {
+    faulted: AtomicBool,
+    last_error: Mutex<Option<StreamErrorEvent>>,
+    on_error: RwLock<Option<StreamErrorCallback>>,
+    ended: AtomicBool,
+    on_ended: RwLock<Option<EndedCallback>>,
+    clock: BlockClock,
+}
+
+impl StreamStatus {
+    pub(crate) fn new() -> Arc<Self> {
+        Arc::new(StreamStatus {
+            faulted: AtomicBool::new(false),
+            last_error: Mutex::new(None),
+            on_error: RwLock::new(None),
+            ended: AtomicBool::new(false),
+            on_ended: RwLock::new(None),
+            clock: BlockClock::default(),
+        })
+    }
+
+    pub(crate) fn record_input(&self, info: &cpal::InputCallbackInfo, frames: usize) {
+        let ts = info.timestamp();
+        self.clock
+            .store(ts.callback.into(), ts.capture.into(), frames);
+    }
+
+    pub(crate) fn record_output(&self, info: &cpal::OutputCallbackInfo, frames: usize) {
+        let ts = info.timestamp();
+        self.clock
+            .store(ts.callback.into(), ts.playback.into(), frames);
+    }
+
+    pub(crate) fn error_handler(
+        self: &Arc<Self>,
+    ) -> impl FnMut(cpal::StreamError) + Send + 'static {
+        let status = self.clone();
+        move |err| status.report(&err)
+    }
+
+    /// Marks the stream's source as exhausted and notifies `onEnded`, once.
+    ///
+    /// Takes a lock, so call it from a worker thread rather than the audio callback.
+    pub(crate) fn report_ended(&self) {
+        // Hold the lock so a callback registered concurrently is called exactly once.
+        let callback = self.on_ended.read().unwrap();
+        if !self.ended.swap(true, Ordering::AcqRel) {
+            if let Some(callback) = callback.as_ref() {
+                callback.call((), ThreadsafeFunctionCallMode::NonBlocking);
+            }
+        }
+    }
+
+    fn report(&self, err: &cpal::StreamError) {
+        // An xrun is a glitch, not a reason to consider the stream dead.
+        if !matches!(err, cpal::StreamError::BufferUnderrun) {
+            self.faulted.store(true, Ordering::Release);
+        }
+
+        self.notify(StreamErrorEvent::from(err));
+    }
+
+    pub(crate) fn ended(&self) -> bool {
+        self.ended.load(Ordering::Acquire)
+    }
+
+    /// Reports a failure in work feeding the stream rather than in the backend, such as
+    /// decoding a file; the stream keeps running, so it does not count as a fault.
+    pub(crate) fn report_error(&self, description: String) {
+        self.notify(StreamErrorEvent {
+            kind: StreamError::BackendSpecific,
+            description,
+        });
+    }
+
+    fn notify(&self, event: StreamErrorEvent) {
+        match self.on_error.read().unwrap().as_ref() {
+            Some(callback) => {
+                callback.call(event.clone(), ThreadsafeFunctionCallMode::NonBlocking);
+            }
+            None => eprintln!("an error occurred on stream: {}", event.description),
+        }
+        *self.last_error.lock().unwrap() = Some(event);
+    }
 }
 
+#[napi]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamState {
+    /// Built but never started.
+    Created,
+    Playing,
+    Paused,
+    /// Released with `close()`; the device is free again.
+    Closed,
+    /// The backend reported an error that stopped the stream.
+    Errored,
+}
+
+/// Work that must run once the cpal stream is gone, such as finalizing a file.
+pub(crate) type Finisher = Box<dyn FnOnce() -> Result<(), ErrorCode> + Send>;
+
 #[napi]
 pub struct AudioStream {
     stream: Option<cpal::Stream>,
+    /// The capture side of a duplex stream, started before and stopped with `stream`.
+    input: Option<cpal::Stream>,
+    status: Arc<StreamStatus>,
+    state: StreamState,
+    finisher: Option<Finisher>,
+    /// Set for streams playing a file.
+    playback: Option<Arc<PlaybackQueue>>,
 }
 
 impl AudioStream {
-    pub fn new(stream: cpal::Stream) -> Self {
+    pub(crate) fn new(stream: cpal::Stream, status: Arc<StreamStatus>) -> Self {
         AudioStream {
             stream: Some(stream),
+            input: None,
+            status,
+            state: StreamState::Created,
+            finisher: None,
+            playback: None,
         }
     }
+
+    pub(crate) fn with_finisher(mut self, finisher: Finisher) -> Self {
+        self.finisher = Some(finisher);
+        self
+    }
+
+    pub(crate) fn with_input(mut self, input: cpal::Stream) -> Self {
+        self.input = Some(input);
+        self
+    }
+
+    pub(crate) fn with_playback(mut self, playback: Arc<PlaybackQueue>) -> Self {
+        self.playback = Some(playback);
+        self
+    }
+
+    fn stream(&self) -> Result<&cpal::Stream, ErrorCode> {
+        self.stream
+            .as_ref()
+            .ok_or_else(|| Error::new(ErrorCode::StreamClosed, "Stream is closed"))
+    }
 }
 
 #[napi]
 impl AudioStream {
+    /// Starts the stream; does nothing if it is already playing.
     #[napi]
-    pub fn play(&self) -> Result<()> {
-        if let Some(ref s) = self.stream {
-            s.play()
-                .map_err(|e| Error::from_reason(format!("Failed to play: {}", e)))
-        } else {
-            Err(Error::from_reason("Stream is not initialized"))
+    pub fn play(&mut self) -> Result<(), ErrorCode> {
+        let stream = self.stream()?;
+        if self.state != StreamState::Playing {
+            if let Some(input) = &self.input {
+                input.play().map_err(cpal_error("Failed to play"))?;
+            }
+            stream.play().map_err(cpal_error("Failed to play"))?;
+            self.state = StreamState::Playing;
+        }
+        Ok(())
+    }
+
+    /// Pauses the stream; does nothing if it is already paused.
+    #[napi]
+    pub fn pause(&mut self) -> Result<(), ErrorCode> {
+        let stream = self.stream()?;
+        if self.state != StreamState::Paused {
+            stream.pause().map_err(cpal_error("Failed to pause"))?;
+            if let Some(input) = &self.input {
+                input.pause().map_err(cpal_error("Failed to pause"))?;
+            }
+            self.state = StreamState::Paused;
         }
+        Ok(())
     }
 
+    /// Stops the stream and releases the device. Closing twice is a no-op.
+    ///
+    /// Fails only if work tied to the stream, like finalizing a recording, fails.
     #[napi]
-    pub fn pause(&self) -> Result<()> {
-        if let Some(ref s) = self.stream {
-            s.pause()
-                .map_err(|e| Error::from_reason(format!("Failed to pause: {}", e)))
+    pub fn close(&mut self) -> Result<(), ErrorCode> {
+        self.stream = None;
+        self.input = None;
+        self.state = StreamState::Closed;
+        *self.status.on_error.write().unwrap() = None;
+        *self.status.on_ended.write().unwrap() = None;
+        match self.finisher.take() {
+            Some(finish) => finish(),
+            None => Ok(()),
+        }
+    }
+
+    /// Alias of `close()`; also installed as `Symbol.dispose` so `using` works.
+    #[napi]
+    pub fn dispose(&mut self) -> Result<(), ErrorCode> {
+        self.close()
+    }
+
+    /// Alias of `close()`, reads better for recordings.
+    #[napi]
+    pub fn stop(&mut self) -> Result<(), ErrorCode> {
+        self.close()
+    }
+
+    #[napi(getter)]
+    pub fn state(&self) -> StreamState {
+        if self.state != StreamState::Closed && self.faulted() {
+            StreamState::Errored
+        } else {
+            self.state
+        }
+    }
+
+    /// Registers a callback for errors reported by the backend while the stream runs.
+    #[napi(ts_args_type = "callback: (error: StreamErrorEvent) => void")]
+    pub fn on_error(&self, callback: Function<'_, StreamErrorEvent, ()>) -> Result<()> {
+        let callback = callback
+            .build_threadsafe_function()
+            .callee_handled::<false>()
+            .weak::<true>()
+            .build()?;
+        *self.status.on_error.write().unwrap() = Some(callback);
+        Ok(())
+    }
+
+    /// Registers a callback for when a stream playing a file reaches its end.
+    ///
+    /// Called right away if the stream has already ended; looping streams never end.
+    #[napi(ts_args_type = "callback: () => void")]
+    pub fn on_ended(&self, callback: Function<'_, (), ()>) -> Result<()> {
+        let callback: EndedCallback = callback
+            .build_threadsafe_function()
+            .callee_handled::<false>()
+            .weak::<true>()
+            .build()?;
+        let mut slot = self.status.on_ended.write().unwrap();
+        if self.status.ended.load(Ordering::Acquire) {
+            callback.call((), ThreadsafeFunctionCallMode::NonBlocking);
         } else {
-            Err(Error::from_reason("Stream is not initialized"))
+            *slot = Some(callback);
         }
+        Ok(())
+    }
+
+    /// Continues a stream playing a file from `seconds` into the file; `onEnded` can fire again.
+    #[napi]
+    pub fn seek(&self, seconds: f64) -> Result<(), ErrorCode> {
+        self.stream()?;
+        let playback = self.playback.as_ref().ok_or_else(|| {
+            Error::new(
+                ErrorCode::InvalidArgument,
+                "Only streams created with playFile can seek",
+            )
+        })?;
+        // Clear the player's end flag first so the callback cannot report the old end again.
+        playback.seek(seconds);
+        self.status.ended.store(false, Ordering::Release);
+        Ok(())
+    }
+
+    /// Whether a stream playing a file has played all of it.
+    #[napi(getter)]
+    pub fn ended(&self) -> bool {
+        self.status.ended()
+    }
+
+    /// Whether the backend reported an error that stopped the stream (xruns excluded).
+    #[napi(getter)]
+    pub fn faulted(&self) -> bool {
+        self.status.faulted.load(Ordering::Acquire)
+    }
+
+    /// Timing of the most recent block, or `null` before the first callback.
+    #[napi(getter)]
+    pub fn last_block(&self) -> Option<BlockTimestamp> {
+        self.status.clock.load()
+    }
+
+    #[napi(getter)]
+    pub fn last_error(&self) -> Option<StreamErrorEvent> {
+        self.status.last_error.lock().unwrap().clone()
+    }
+}
+
+/// Points `Symbol.dispose` at `close` on the prototypes of closable classes, on runtimes
+/// that have it.
+#[napi(module_exports)]
+pub fn install_dispose(exports: Object, env: Env) -> Result<()> {
+    let symbol: Object = env.get_global()?.get_named_property("Symbol")?;
+    let dispose: Unknown = symbol.get_named_property("dispose")?;
+    if dispose.get_type()? == ValueType::Undefined {
+        return Ok(());
+    }
+    for name in ["AudioStream", "DeviceWatcher"] {
+        let class: Object = exports.get_named_property(name)?;
+        let mut prototype: Object = class.get_named_property("prototype")?;
+        let close: Unknown = prototype.get_named_property("close")?;
+        prototype.set_property(dispose, close)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_underrun_does_not_fault() {
+        let status = StreamStatus::new();
+        status.report(&cpal::StreamError::BufferUnderrun);
+        assert!(!status.faulted.load(Ordering::Acquire));
+
+        status.report(&cpal::StreamError::DeviceNotAvailable);
+        assert!(status.faulted.load(Ordering::Acquire));
+        let last = status.last_error.lock().unwrap().clone().unwrap();
+        assert_eq!(last.kind, crate::error::StreamError::DeviceNotAvailable);
+    }
+
+    #[test]
+    fn test_feeder_errors_do_not_fault() {
+        let status = StreamStatus::new();
+        status.report_error("Stopped decoding audio file: bad frame".to_string());
+        assert!(!status.faulted.load(Ordering::Acquire));
+        let last = status.last_error.lock().unwrap().clone().unwrap();
+        assert_eq!(last.kind, crate::error::StreamError::BackendSpecific);
+    }
+
+    #[test]
+    fn test_stream_instant_conversion() {
+        let instant: StreamInstant = cpal::StreamInstant::new(3, 250).into();
+        assert_eq!(
+            instant,
+            StreamInstant {
+                seconds: 3,
+                nanos: 250
+            }
+        );
+    }
+
+    #[test]
+    fn test_instant_nanos_round_trip() {
+        let instant = StreamInstant {
+            seconds: -2,
+            nanos: 750_000_000,
+        };
+        assert_eq!(instant.as_nanos(), -1_250_000_000);
+        assert_eq!(StreamInstant::from_nanos(instant.as_nanos()), instant);
+    }
+
+    #[test]
+    fn test_block_clock_keeps_latest_block() {
+        let clock = BlockClock::default();
+        assert!(clock.load().is_none());
+
+        let callback = StreamInstant::from_nanos(1_000_000_000);
+        clock.store(callback, StreamInstant::from_nanos(1_010_000_000), 480);
+        clock.store(callback, StreamInstant::from_nanos(1_020_000_000), 256);
+
+        let block = clock.load().unwrap();
+        assert_eq!(block.index, 2);
+        assert_eq!(block.frames, 256);
+        assert!((block.latency - 0.02).abs() < 1e-9);
     }
 }
@@ -13,6 +13,8 @@ pub enum SampleFormat {
     U64,
     F32,
     F64,
+    I24,
+    U24,
 }
 
 impl From<cpal::SampleFormat> for SampleFormat {
@@ -28,6 +30,8 @@ impl From<cpal::SampleFormat> for SampleFormat {
             cpal::SampleFormat::U64 => SampleFormat::U64,
             cpal::SampleFormat::F32 => SampleFormat::F32,
             cpal::SampleFormat::F64 => SampleFormat::F64,
+            cpal::SampleFormat::I24 => SampleFormat::I24,
+            cpal::SampleFormat::U24 => SampleFormat::U24,
             _ => SampleFormat::F32,
         }
     }
@@ -46,6 +50,8 @@ impl From<SampleFormat> for cpal::SampleFormat {
             SampleFormat::U64 => cpal::SampleFormat::U64,
             SampleFormat::F32 => cpal::SampleFormat::F32,
             SampleFormat::F64 => cpal::SampleFormat::F64,
+            SampleFormat::I24 => cpal::SampleFormat::I24,
+            SampleFormat::U24 => cpal::SampleFormat::U24,
         }
     }
 }
@@ -60,6 +66,8 @@ pub enum HostId {
     CoreAudio,
     Emscripten,
     Other,
+    /// Virtual devices that need no audio hardware, see `createNullHost`.
+    Null,
 }
 
 impl From<cpal::HostId> for HostId {
@@ -71,6 +79,8 @@ impl From<cpal::HostId> for HostId {
             "asio" => HostId::Asio,
             "coreaudio" => HostId::CoreAudio,
             "emscripten" => HostId::Emscripten,
+            // The null host is the only custom host this crate creates.
+            "custom" => HostId::Null,
             _ => HostId::Other,
         }
     }
@@ -85,6 +95,7 @@ pub fn get_all_hosts() -> Vec<HostId> {
         HostId::Asio,
         HostId::CoreAudio,
         HostId::Emscripten,
+        HostId::Null,
     ]
 }
 
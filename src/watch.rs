@@ -0,0 +1,291 @@
+use crate::callback::DeviceChangeCallback;
+use crate::device::DeviceId;
+use cpal::traits::{DeviceTrait, HostTrait};
+use napi::threadsafe_function::ThreadsafeFunctionCallMode;
+use napi_derive::napi;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+const DEFAULT_POLL_INTERVAL_MS: u32 = 1000;
+
+#[napi]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceChange {
+    Added,
+    Removed,
+    DefaultInputChanged,
+    DefaultOutputChanged,
+}
+
+#[napi(object)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceChangeEvent {
+    pub change: DeviceChange,
+    /// The device added or removed, or the new default; `null` when there is no default left.
+    pub id: Option<DeviceId>,
+    pub name: Option<String>,
+}
+
+#[napi(object)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WatchDevicesOptions {
+    /// How often the device list is polled, in milliseconds (default 1000).
+    pub interval_ms: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct DeviceEntry {
+    id: String,
+    name: String,
+}
+
+impl DeviceEntry {
+    fn new(device: &cpal::Device) -> Self {
+        let name = device
+            .description()
+            .map(|d| d.name().to_string())
+            .unwrap_or_default();
+        DeviceEntry {
+            // Devices without a stable id are told apart by name.
+            id: device
+                .id()
+                .map(|id| id.to_string())
+                .unwrap_or_else(|_| name.clone()),
+            name,
+        }
+    }
+
+    fn event(entry: Option<&DeviceEntry>, change: DeviceChange) -> DeviceChangeEvent {
+        DeviceChangeEvent {
+            change,
+            id: entry.map(|e| DeviceId { id: e.id.clone() }),
+            name: entry.map(|e| e.name.clone()),
+        }
+    }
+}
+
+/// The devices of a host at one moment.
+#[derive(Debug, Default, PartialEq)]
+struct DeviceSnapshot {
+    devices: Vec<DeviceEntry>,
+    default_input: Option<DeviceEntry>,
+    default_output: Option<DeviceEntry>,
+}
+
+impl DeviceSnapshot {
+    /// `None` when the host fails to list its devices right now.
+    fn take(host: &cpal::Host) -> Option<Self> {
+        let devices = host.devices().ok()?;
+        Some(DeviceSnapshot {
+            devices: devices.map(|d| DeviceEntry::new(&d)).collect(),
+            default_input: host.default_input_device().map(|d| DeviceEntry::new(&d)),
+            default_output: host.default_output_device().map(|d| DeviceEntry::new(&d)),
+        })
+    }
+
+    /// Moves `current` on to `next` and returns what changed. A failed listing keeps the
+    /// last good snapshot, so a transient error does not report every device as removed
+    /// and then added again.
+    fn advance(current: &mut Option<Self>, next: Option<Self>) -> Vec<DeviceChangeEvent> {
+        let Some(next) = next else {
+            return Vec::new();
+        };
+        let events = match current {
+            Some(previous) => previous.changes(&next),
+            None => Vec::new(),
+        };
+        *current = Some(next);
+        events
+    }
+
+    /// What changed between `self` and `next`: removals, additions, then default changes.
+    fn changes(&self, next: &DeviceSnapshot) -> Vec<DeviceChangeEvent> {
+        let removed = self
+            .devices
+            .iter()
+            .filter(|d| !next.devices.iter().any(|n| n.id == d.id))
+            .map(|d| DeviceEntry::event(Some(d), DeviceChange::Removed));
+        let added = next
+            .devices
+            .iter()
+            .filter(|n| !self.devices.iter().any(|d| d.id == n.id))
+            .map(|n| DeviceEntry::event(Some(n), DeviceChange::Added));
+        let mut events: Vec<_> = removed.chain(added).collect();
+        let default_id = |entry: &Option<DeviceEntry>| entry.as_ref().map(|e| e.id.clone());
+        if default_id(&self.default_input) != default_id(&next.default_input) {
+            events.push(DeviceEntry::event(
+                next.default_input.as_ref(),
+                DeviceChange::DefaultInputChanged,
+            ));
+        }
+        if default_id(&self.default_output) != default_id(&next.default_output) {
+            events.push(DeviceEntry::event(
+                next.default_output.as_ref(),
+                DeviceChange::DefaultOutputChanged,
+            ));
+        }
+        events
+    }
+}
+
+/// Reports device changes of a host until closed.
+#[napi]
+pub struct DeviceWatcher {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl DeviceWatcher {
+    /// Takes the first snapshot right away, so only changes after this call are reported.
+    pub(crate) fn spawn(
+        host: Arc<cpal::Host>,
+        callback: DeviceChangeCallback,
+        options: WatchDevicesOptions,
+    ) -> Self {
+        let interval = Duration::from_millis(
+            options
+                .interval_ms
+                .unwrap_or(DEFAULT_POLL_INTERVAL_MS)
+                .max(1) as u64,
+        );
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let mut snapshot = DeviceSnapshot::take(&host);
+        let handle = std::thread::spawn(move || loop {
+            std::thread::park_timeout(interval);
+            if stopped.load(Ordering::Acquire) {
+                break;
+            }
+            let next = DeviceSnapshot::take(&host);
+            for event in DeviceSnapshot::advance(&mut snapshot, next) {
+                callback.call(event, ThreadsafeFunctionCallMode::NonBlocking);
+            }
+        });
+        DeviceWatcher {
+            stop,
+            handle: Some(handle),
+        }
+    }
+}
+
+#[napi]
+impl DeviceWatcher {
+    /// Stops polling. Events already queued for the JS thread may still arrive after
+    /// this returns.
+    #[napi]
+    pub fn close(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(handle) = self.handle.take() {
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+    }
+
+    #[napi(getter)]
+    pub fn active(&self) -> bool {
+        self.handle.is_some()
+    }
+}
+
+impl Drop for DeviceWatcher {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: &str) -> DeviceEntry {
+        DeviceEntry {
+            id: id.to_string(),
+            name: id.to_uppercase(),
+        }
+    }
+
+    #[test]
+    fn test_changes_between_snapshots() {
+        let before = DeviceSnapshot {
+            devices: vec![entry("a"), entry("b")],
+            default_input: Some(entry("a")),
+            default_output: Some(entry("b")),
+        };
+        let after = DeviceSnapshot {
+            devices: vec![entry("b"), entry("c")],
+            default_input: None,
+            default_output: Some(entry("b")),
+        };
+        let changes: Vec<_> = before
+            .changes(&after)
+            .into_iter()
+            .map(|e| (e.change, e.id.map(|id| id.id)))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                (DeviceChange::Removed, Some("a".to_string())),
+                (DeviceChange::Added, Some("c".to_string())),
+                (DeviceChange::DefaultInputChanged, None),
+            ]
+        );
+        assert!(after.changes(&after).is_empty());
+    }
+
+    #[test]
+    fn test_failed_listing_keeps_snapshot() {
+        let before = DeviceSnapshot {
+            devices: vec![entry("a"), entry("b")],
+            ..Default::default()
+        };
+        let mut current = Some(before);
+        assert!(DeviceSnapshot::advance(&mut current, None).is_empty());
+        assert_eq!(current.as_ref().unwrap().devices.len(), 2);
+
+        let after = DeviceSnapshot {
+            devices: vec![entry("a"), entry("b"), entry("c")],
+            ..Default::default()
+        };
+        let changes = DeviceSnapshot::advance(&mut current, Some(after));
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].change, DeviceChange::Added);
+    }
+
+    #[test]
+    fn test_snapshot_follows_null_host() {
+        let host = crate::null_host::create_null_host(None);
+        let first = DeviceSnapshot::take(&host.inner).unwrap();
+        assert_eq!(first.devices.len(), 1);
+
+        host.connect_null_device(crate::null_host::NullDeviceOptions {
+            name: Some("Headset".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+        let second = DeviceSnapshot::take(&host.inner).unwrap();
+        let changes = first.changes(&second);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].change, DeviceChange::Added);
+        assert_eq!(changes[0].name.as_deref(), Some("Headset"));
+
+        assert!(host
+            .disconnect_null_device("Null Device".to_string())
+            .unwrap());
+        let third = DeviceSnapshot::take(&host.inner).unwrap();
+        let changes: Vec<_> = second
+            .changes(&third)
+            .into_iter()
+            .map(|e| e.change)
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                DeviceChange::Removed,
+                DeviceChange::DefaultInputChanged,
+                DeviceChange::DefaultOutputChanged,
+            ]
+        );
+    }
+}
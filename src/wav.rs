@@ -0,0 +1,351 @@
+use cpal::{Sample, I24};
+use napi_derive::napi;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Bytes before the fmt chunk: RIFF header and JUNK (room for ds64).
+const FMT_OFFSET: u64 = 12 + 36;
+const JUNK_OFFSET: u64 = 12;
+const FMT_LEN: u32 = 16;
+/// fmt body of a WAVE_FORMAT_EXTENSIBLE file, with valid bits, channel mask and GUID.
+const EXTENSIBLE_FMT_LEN: u32 = 40;
+const RIFF_LIMIT: u64 = u32::MAX as u64;
+const FORMAT_PCM: u16 = 1;
+const FORMAT_IEEE_FLOAT: u16 = 3;
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+/// Bytes of the KSDATAFORMAT_SUBTYPE GUIDs after the leading format code.
+const SUBFORMAT_SUFFIX: [u8; 14] = [
+    0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+];
+
+/// Speaker positions for the usual layouts of each channel count; none past 7.1.
+fn channel_mask(channels: u16) -> u32 {
+    match channels {
+        1 => 0x4,
+        2 => 0x3,
+        3 => 0x7,
+        4 => 0x33,
+        5 => 0x37,
+        6 => 0x3F,
+        7 => 0x70F,
+        8 => 0x63F,
+        _ => 0,
+    }
+}
+
+/// Sample encoding of a WAV file.
+#[napi]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WavFormat {
+    #[default]
+    Int16,
+    Int24,
+    Int32,
+    Float32,
+}
+
+impl WavFormat {
+    fn bytes_per_sample(self) -> u16 {
+        match self {
+            WavFormat::Int16 => 2,
+            WavFormat::Int24 => 3,
+            WavFormat::Int32 | WavFormat::Float32 => 4,
+        }
+    }
+
+    fn encode(self, sample: f32, out: &mut Vec<u8>) {
+        let sample = sample.clamp(-1.0, 1.0);
+        match self {
+            WavFormat::Int16 => out.extend(i16::from_sample::<f32>(sample).to_le_bytes()),
+            WavFormat::Int24 => {
+                out.extend(&I24::from_sample::<f32>(sample).inner().to_le_bytes()[..3])
+            }
+            WavFormat::Int32 => out.extend(i32::from_sample::<f32>(sample).to_le_bytes()),
+            WavFormat::Float32 => out.extend(sample.to_le_bytes()),
+        }
+    }
+}
+
+/// Streams interleaved `f32` samples into a WAV file.
+///
+/// The header is written with zero sizes up front and patched in `finalize`; a JUNK
+/// chunk reserves room for the ds64 chunk so files over 4 GiB become RF64 in place.
+pub(crate) struct WavWriter {
+    file: BufWriter<File>,
+    format: WavFormat,
+    channels: u16,
+    header_len: u64,
+    data_bytes: u64,
+    scratch: Vec<u8>,
+}
+
+impl WavWriter {
+    pub(crate) fn create(
+        path: impl AsRef<Path>,
+        channels: u16,
+        sample_rate: u32,
+        format: WavFormat,
+    ) -> io::Result<Self> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidInput, message);
+        if channels == 0 {
+            return Err(invalid("A WAV file needs at least one channel"));
+        }
+        let block_align = channels
+            .checked_mul(format.bytes_per_sample())
+            .ok_or_else(|| invalid("Too many channels for a WAV file"))?;
+        let byte_rate = u32::try_from(sample_rate as u64 * block_align as u64)
+            .map_err(|_| invalid("Byte rate too high for a WAV file"))?;
+        let mut file = BufWriter::new(File::create(path)?);
+        let bits = format.bytes_per_sample() * 8;
+        let tag = match format {
+            WavFormat::Float32 => FORMAT_IEEE_FLOAT,
+            _ => FORMAT_PCM,
+        };
+        // Readers may ignore the layout or reject deep samples in a plain fmt chunk.
+        let extensible = bits > 16 || channels > 2;
+        let fmt_len = if extensible {
+            EXTENSIBLE_FMT_LEN
+        } else {
+            FMT_LEN
+        };
+        file.write_all(b"RIFF")?;
+        file.write_all(&0u32.to_le_bytes())?;
+        file.write_all(b"WAVE")?;
+        file.write_all(b"JUNK")?;
+        file.write_all(&28u32.to_le_bytes())?;
+        file.write_all(&[0; 28])?;
+        file.write_all(b"fmt ")?;
+        file.write_all(&fmt_len.to_le_bytes())?;
+        file.write_all(&(if extensible { FORMAT_EXTENSIBLE } else { tag }).to_le_bytes())?;
+        file.write_all(&channels.to_le_bytes())?;
+        file.write_all(&sample_rate.to_le_bytes())?;
+        file.write_all(&byte_rate.to_le_bytes())?;
+        file.write_all(&block_align.to_le_bytes())?;
+        file.write_all(&bits.to_le_bytes())?;
+        if extensible {
+            file.write_all(&22u16.to_le_bytes())?;
+            file.write_all(&bits.to_le_bytes())?;
+            file.write_all(&channel_mask(channels).to_le_bytes())?;
+            file.write_all(&tag.to_le_bytes())?;
+            file.write_all(&SUBFORMAT_SUFFIX)?;
+        }
+        file.write_all(b"data")?;
+        file.write_all(&0u32.to_le_bytes())?;
+        Ok(WavWriter {
+            file,
+            format,
+            channels,
+            header_len: FMT_OFFSET + 8 + fmt_len as u64 + 8,
+            data_bytes: 0,
+            scratch: Vec::new(),
+        })
+    }
+
+    pub(crate) fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        self.scratch.clear();
+        for sample in samples {
+            self.format.encode(*sample, &mut self.scratch);
+        }
+        self.file.write_all(&self.scratch)?;
+        self.data_bytes += self.scratch.len() as u64;
+        Ok(())
+    }
+
+    /// Pads the data chunk to an even length, fills in the chunk sizes and flushes the file.
+    pub(crate) fn finalize(self) -> io::Result<()> {
+        let rf64 = self.riff_bytes() > RIFF_LIMIT;
+        self.finish(rf64)
+    }
+
+    /// Everything after the RIFF size field, including the data chunk's pad byte.
+    fn riff_bytes(&self) -> u64 {
+        self.header_len - 8 + self.data_bytes + self.data_bytes % 2
+    }
+
+    fn finish(mut self, rf64: bool) -> io::Result<()> {
+        if self.data_bytes % 2 == 1 {
+            self.file.write_all(&[0])?;
+        }
+        let riff_bytes = self.riff_bytes();
+        let data_size_offset = self.header_len - 4;
+        if rf64 {
+            let frames =
+                self.data_bytes / (self.channels as u64 * self.format.bytes_per_sample() as u64);
+            self.file.seek(SeekFrom::Start(0))?;
+            self.file.write_all(b"RF64")?;
+            self.file.write_all(&u32::MAX.to_le_bytes())?;
+            self.file.seek(SeekFrom::Start(JUNK_OFFSET))?;
+            self.file.write_all(b"ds64")?;
+            self.file.write_all(&28u32.to_le_bytes())?;
+            self.file.write_all(&riff_bytes.to_le_bytes())?;
+            self.file.write_all(&self.data_bytes.to_le_bytes())?;
+            self.file.write_all(&frames.to_le_bytes())?;
+            self.file.write_all(&0u32.to_le_bytes())?;
+            self.file.seek(SeekFrom::Start(data_size_offset))?;
+            self.file.write_all(&u32::MAX.to_le_bytes())?;
+        } else {
+            self.file.seek(SeekFrom::Start(4))?;
+            self.file.write_all(&(riff_bytes as u32).to_le_bytes())?;
+            self.file.seek(SeekFrom::Start(data_size_offset))?;
+            self.file
+                .write_all(&(self.data_bytes as u32).to_le_bytes())?;
+        }
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Header length of a file with a plain fmt chunk.
+    const PLAIN_HEADER_LEN: usize = 80;
+    /// Header length of a WAVE_FORMAT_EXTENSIBLE file.
+    const EXTENSIBLE_HEADER_LEN: usize = 104;
+
+    fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn write_file(
+        name: &str,
+        channels: u16,
+        format: WavFormat,
+        samples: &[f32],
+        rf64: bool,
+    ) -> Vec<u8> {
+        let path = std::env::temp_dir().join(name);
+        let mut writer = WavWriter::create(&path, channels, 48000, format).unwrap();
+        writer.write(samples).unwrap();
+        writer.finish(rf64).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_writes_float_header_and_samples() {
+        let bytes = write_file(
+            "cpal-napi-wav-float.wav",
+            2,
+            WavFormat::Float32,
+            &[0.5, -0.5],
+            false,
+        );
+        let header_len = EXTENSIBLE_HEADER_LEN;
+        assert_eq!(bytes.len(), header_len + 8);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(read_u32(&bytes, 4) as usize, header_len);
+        assert_eq!(&bytes[12..16], b"JUNK");
+        assert_eq!(read_u16(&bytes, 56), FORMAT_EXTENSIBLE);
+        // The SubFormat GUID starts with the IEEE float format code.
+        assert_eq!(read_u16(&bytes, 80), FORMAT_IEEE_FLOAT);
+        assert_eq!(&bytes[82..96], &SUBFORMAT_SUFFIX);
+        assert_eq!(read_u32(&bytes, header_len - 4), 8);
+        assert_eq!(
+            f32::from_le_bytes(bytes[header_len..header_len + 4].try_into().unwrap()),
+            0.5
+        );
+    }
+
+    #[test]
+    fn test_rejects_unwritable_formats() {
+        let path = std::env::temp_dir().join("cpal-napi-wav-invalid.wav");
+        let no_channels = WavWriter::create(&path, 0, 48000, WavFormat::Int16);
+        assert_eq!(
+            no_channels.err().unwrap().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        let too_fast = WavWriter::create(&path, 8, u32::MAX, WavFormat::Float32);
+        assert_eq!(too_fast.err().unwrap().kind(), io::ErrorKind::InvalidInput);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_writes_plain_fmt_for_16_bit_stereo() {
+        let bytes = write_file(
+            "cpal-napi-wav-16.wav",
+            2,
+            WavFormat::Int16,
+            &[0.5, -0.5],
+            false,
+        );
+        assert_eq!(bytes.len(), PLAIN_HEADER_LEN + 4);
+        assert_eq!(read_u32(&bytes, 52), FMT_LEN);
+        assert_eq!(read_u16(&bytes, 56), FORMAT_PCM);
+        assert_eq!(&bytes[72..76], b"data");
+    }
+
+    #[test]
+    fn test_writes_24_bit_samples() {
+        let bytes = write_file(
+            "cpal-napi-wav-24.wav",
+            2,
+            WavFormat::Int24,
+            &[0.5, -0.5],
+            false,
+        );
+        assert_eq!(bytes.len(), EXTENSIBLE_HEADER_LEN + 6);
+        assert_eq!(read_u16(&bytes, 70), 24);
+        assert_eq!(read_u16(&bytes, 74), 24, "valid bits");
+        assert_eq!(read_u32(&bytes, 76), 0x3, "channel mask");
+        assert_eq!(read_u16(&bytes, 80), FORMAT_PCM);
+        let first = EXTENSIBLE_HEADER_LEN;
+        assert_eq!(&bytes[first..first + 3], &[0x00, 0x00, 0x40]);
+    }
+
+    #[test]
+    fn test_multichannel_16_bit_is_extensible() {
+        let bytes = write_file(
+            "cpal-napi-wav-51.wav",
+            6,
+            WavFormat::Int16,
+            &[0.0; 6],
+            false,
+        );
+        assert_eq!(read_u32(&bytes, 52), EXTENSIBLE_FMT_LEN);
+        assert_eq!(read_u16(&bytes, 56), FORMAT_EXTENSIBLE);
+        assert_eq!(read_u32(&bytes, 76), 0x3F);
+        assert_eq!(&bytes[96..100], b"data");
+    }
+
+    #[test]
+    fn test_pads_odd_data_chunk() {
+        let bytes = write_file("cpal-napi-wav-odd.wav", 1, WavFormat::Int24, &[0.5], false);
+        let header_len = EXTENSIBLE_HEADER_LEN;
+        assert_eq!(bytes.len(), header_len + 4);
+        assert_eq!(
+            read_u32(&bytes, header_len - 4),
+            3,
+            "data size excludes the pad"
+        );
+        assert_eq!(read_u32(&bytes, 4) as usize, header_len - 8 + 4);
+        assert_eq!(bytes[header_len + 3], 0);
+    }
+
+    #[test]
+    fn test_rf64_header() {
+        let bytes = write_file(
+            "cpal-napi-wav-rf64.wav",
+            2,
+            WavFormat::Int16,
+            &[0.5, -0.5],
+            true,
+        );
+        assert_eq!(&bytes[0..4], b"RF64");
+        assert_eq!(read_u32(&bytes, 4), u32::MAX);
+        assert_eq!(&bytes[12..16], b"ds64");
+        assert_eq!(
+            u64::from_le_bytes(bytes[28..36].try_into().unwrap()),
+            4,
+            "data size"
+        );
+        assert_eq!(u64::from_le_bytes(bytes[36..44].try_into().unwrap()), 1);
+        assert_eq!(read_u32(&bytes, PLAIN_HEADER_LEN - 4), u32::MAX);
+    }
+}
@@ -1,11 +1,31 @@
 import { expect, test, describe } from "bun:test";
+import { readFileSync, rmSync, writeFileSync } from "node:fs";
+import { tmpdir } from "node:os";
+import { join } from "node:path";
 import {
   availableHosts,
   getDefaultHost,
   AudioBuffer,
+  WatermarkEvent,
   hostFromId,
   getAllHosts,
+  createNullHost,
+  OfflineRenderer,
+  AudioEncoding,
+  probeAudioFile,
+  resample,
+  ResampleQuality,
+  WavFormat,
   HostId,
+  StreamState,
+  SampleFormat,
+  ConfigStrategy,
+  DeviceChange,
+  DeviceDirection,
+  DeviceType,
+  type DeviceChangeEvent,
+  StreamError,
+  type StreamErrorEvent,
   I24,
   U24,
 } from "../index";
@@ -70,6 +90,74 @@ describe("Audio Library Tests", () => {
     expect(buffer.length()).toBe(0);
   });
 
+  test("AudioBuffer should read samples in FIFO order", async () => {
+    const buffer = new AudioBuffer();
+    buffer.push(new Float32Array([1, 2, 3, 4, 5, 6]));
+
+    expect(Array.from(buffer.peek(2))).toEqual([1, 2]);
+    expect(Array.from(buffer.read(2))).toEqual([1, 2]);
+
+    const out = new Float32Array(2);
+    expect(buffer.readInto(out)).toBe(2);
+    expect(Array.from(out)).toEqual([3, 4]);
+
+    const pending = buffer.readExact(3);
+    buffer.push(new Float32Array([7]));
+    expect(Array.from(await pending)).toEqual([5, 6, 7]);
+
+    buffer.push(new Float32Array([8, 9]));
+    expect(Array.from(buffer.drainAll())).toEqual([8, 9]);
+    expect(buffer.length()).toBe(0);
+  });
+
+  test("AudioBuffer should count overruns when full", () => {
+    const buffer = new AudioBuffer(1, 2);
+    expect(buffer.capacity).toBe(2);
+
+    buffer.push(new Float32Array([1, 2, 3]));
+    expect(buffer.length()).toBe(2);
+    expect(buffer.overruns()).toBe(1);
+    expect(buffer.underruns()).toBe(0);
+  });
+
+  test("AudioBuffer push should report accepted samples", () => {
+    const buffer = new AudioBuffer(2, 2);
+    expect(buffer.push(new Float32Array([1, 2, 3, 4, 5, 6]))).toBe(4);
+  });
+
+  test("AudioBuffer should emit watermark events", async () => {
+    const buffer = new AudioBuffer(1, 16);
+    const events: WatermarkEvent[] = [];
+    buffer.setWatermarks({ low: 2, high: 8 }, (event) => events.push(event));
+
+    buffer.push(new Float32Array(10));
+    buffer.read(9);
+    await new Promise((resolve) => setTimeout(resolve, 10));
+
+    expect(events).toEqual([WatermarkEvent.High, WatermarkEvent.Low]);
+  });
+
+  test("AudioBuffer rejects invalid watermarks and oversized reads", async () => {
+    const buffer = new AudioBuffer(1, 16);
+    try {
+      buffer.setWatermarks({ low: 8, high: 8 }, () => {});
+      throw new Error("setWatermarks should throw");
+    } catch (e: any) {
+      expect(e.code).toBe("InvalidArgument");
+    }
+
+    const error = await buffer.readExact(17).catch((e) => e);
+    expect(error.code).toBe("InvalidArgument");
+  });
+
+  test("AudioBuffer readExact rejects with Timeout", async () => {
+    const buffer = new AudioBuffer(1, 16);
+    buffer.push(new Float32Array(2));
+    const error = await buffer.readExact(4, 20).catch((e) => e);
+    expect(error.code).toBe("Timeout");
+    expect(buffer.length()).toBe(2);
+  });
+
   test("I24 and U24 types should work", () => {
     const i24 = new I24(0x12345678);
     expect(i24.toI32()).toBe(0x12345678 & 0xFFFFFF);
@@ -139,5 +227,447 @@ describe("Audio Library Tests", () => {
       }
     }
   });
-});
 
+  test("Stream lifecycle", () => {
+    if (IS_CI) return; // Skip in CI
+
+    const host = getDefaultHost();
+    const output = host.defaultOutputDevice();
+    if (output) {
+      let stream;
+      try {
+        stream = output.createBeepStream();
+      } catch (e) {
+        console.warn("Could not create beep stream:", e);
+        return;
+      }
+      expect(stream.state).toBe(StreamState.Created);
+      expect(stream.lastBlock).toBeNull();
+      stream.play();
+      stream.play();
+      expect(stream.state).toBe(StreamState.Playing);
+      stream.pause();
+      expect(stream.state).toBe(StreamState.Paused);
+
+      stream.close();
+      stream.close();
+      expect(stream.state).toBe(StreamState.Closed);
+      expect(() => stream.play()).toThrow();
+      try {
+        stream.play();
+      } catch (e: any) {
+        expect(e.code).toBe("StreamClosed");
+      }
+    }
+  });
+
+  test("Callback output stream pulls blocks from the callback", async () => {
+    const host = createNullHost({ devices: [{ name: "Speakers", inputChannels: 0 }], speed: 20 });
+    const output = host.defaultOutputDevice()!;
+    const config = output.defaultOutputConfig();
+    const sizes: number[] = [];
+    const stream = output.createOutputStreamWithCallback(
+      config,
+      (frames) => {
+        sizes.push(frames);
+        return new Float32Array(frames * config.channels);
+      },
+      { blockFrames: 256, prerollBlocks: 2 },
+    );
+    const prerolled = sizes.length;
+    expect(prerolled).toBeGreaterThan(0);
+
+    stream.play();
+    await new Promise((resolve) => setTimeout(resolve, 100));
+    stream.close();
+    expect(sizes.length).toBeGreaterThan(prerolled);
+    expect(sizes.every((frames) => frames >= 256)).toBe(true);
+  });
+
+  test("Callback input stream hands captured blocks to the callback", async () => {
+    const host = createNullHost({
+      devices: [{ name: "Mic", outputChannels: 0, frequency: 440 }],
+      speed: 20,
+    });
+    const input = host.defaultInputDevice()!;
+    const config = input.defaultInputConfig();
+    const blocks: { data: Float32Array; latency: number; timestamp: unknown }[] = [];
+    const stream = input.createInputStreamWithCallback(config, (data, info) => {
+      blocks.push({ data, latency: info.latency, timestamp: info.timestamp });
+    });
+
+    stream.play();
+    await new Promise((resolve) => setTimeout(resolve, 100));
+    stream.close();
+    expect(blocks.length).toBeGreaterThan(0);
+    for (const block of blocks) {
+      expect(block.data).toBeInstanceOf(Float32Array);
+      expect(block.data.length % config.channels).toBe(0);
+      expect(block.timestamp).toBeDefined();
+      expect(block.latency).toBeGreaterThanOrEqual(0);
+    }
+    expect(blocks.some((block) => block.data.some((sample) => sample !== 0))).toBe(true);
+  });
+
+  test("Null host records and plays without hardware", async () => {
+    const host = createNullHost({
+      devices: [{ name: "Null Mic", inputChannels: 1, outputChannels: 2, frequency: 440 }],
+      speed: 10,
+    });
+    expect(host.name()).toBe("Null");
+    expect(hostFromId(HostId.Null).devices().length).toBe(1);
+
+    const device = host.defaultInputDevice()!;
+    expect(device.name()).toBe("Null Mic");
+
+    const buffer = new AudioBuffer(1);
+    const input = device.createInputStream(device.defaultInputConfig(), buffer);
+    input.play();
+    const recorded = await buffer.readExact(4800);
+    input.close();
+    expect(recorded.length).toBe(4800);
+    expect(recorded.some((s) => s !== 0)).toBe(true);
+    expect(input.lastBlock!.frames).toBe(480);
+
+    const output = device.createOutputStream(device.defaultOutputConfig(), buffer);
+    buffer.push(recorded);
+    output.play();
+    await new Promise((resolve) => setTimeout(resolve, 50));
+    output.close();
+    expect(buffer.frames()).toBeLessThan(4800);
+  });
+
+  test("watchDevices reports hotplug and default changes", async () => {
+    const host = createNullHost({ devices: [{ name: "Speakers", inputChannels: 0 }] });
+    const events: DeviceChangeEvent[] = [];
+    const watcher = host.watchDevices((event) => events.push(event), { intervalMs: 10 });
+
+    host.connectNullDevice({ name: "Headset" });
+    await new Promise((resolve) => setTimeout(resolve, 100));
+    expect(events.map((e) => [e.change, e.name])).toEqual([
+      [DeviceChange.Added, "Headset"],
+      [DeviceChange.DefaultInputChanged, "Headset"],
+    ]);
+
+    events.length = 0;
+    expect(host.disconnectNullDevice("Speakers")).toBe(true);
+    await new Promise((resolve) => setTimeout(resolve, 100));
+    expect(events.map((e) => [e.change, e.name])).toEqual([
+      [DeviceChange.Removed, "Speakers"],
+      [DeviceChange.DefaultOutputChanged, "Headset"],
+    ]);
+
+    watcher.close();
+    expect(watcher.active).toBe(false);
+    expect(() => getDefaultHost().connectNullDevice({ name: "x" })).toThrow();
+  });
+
+  test("deviceById and deviceByName restore a saved device", () => {
+    const host = createNullHost({ devices: [{ name: "Speakers" }, { name: "USB Mic 2" }] });
+    const saved = host.deviceByName("usb mic").id();
+    expect(host.deviceById(saved).name()).toBe("USB Mic 2");
+    expect(host.deviceById(saved.id).name()).toBe("USB Mic 2");
+
+    host.disconnectNullDevice("USB Mic 2");
+    host.connectNullDevice({ name: "USB Mic 3" });
+    expect(host.deviceById(saved).name()).toBe("USB Mic 3");
+
+    try {
+      host.deviceByName("Headphones");
+      throw new Error("expected a lookup error");
+    } catch (e: any) {
+      expect(e.code).toBe("DeviceNotAvailable");
+    }
+  });
+
+  test("Null device description reports a virtual device", () => {
+    const host = createNullHost({ devices: [{ name: "Speakers", inputChannels: 0 }] });
+    const desc = host.devices()[0].description();
+    expect(desc.name).toBe("Speakers");
+    expect(desc.direction).toBe(DeviceDirection.Output);
+    expect(desc.deviceType).toBe(DeviceType.Virtual);
+    expect(desc.driver).toBe("null");
+    expect(Array.isArray(desc.extended)).toBe(true);
+  });
+
+  test("Duplex stream hands captured input to the callback", async () => {
+    const host = createNullHost({ devices: [{ name: "Interface", frequency: 440 }], speed: 20 });
+    const device = host.devices()[0];
+    const config = device.defaultOutputConfig();
+    let blocks = 0;
+    let heard = false;
+    const stream = device.createDuplexStream(
+      device,
+      config,
+      (input) => {
+        blocks++;
+        heard ||= input.some((sample) => sample !== 0);
+        return input;
+      },
+      { blockFrames: 256 },
+    );
+    stream.play();
+    await new Promise((resolve) => setTimeout(resolve, 200));
+    stream.close();
+    expect(blocks).toBeGreaterThan(0);
+    expect(heard).toBe(true);
+  });
+
+  test("Duplex stream reports blocks of the wrong length", async () => {
+    const host = createNullHost({ devices: [{ name: "Interface", frequency: 440 }], speed: 20 });
+    const device = host.devices()[0];
+    const stream = device.createDuplexStream(
+      device,
+      device.defaultOutputConfig(),
+      () => new Float32Array(3),
+      { blockFrames: 256 },
+    );
+    const reported = new Promise<StreamErrorEvent>((resolve) => stream.onError(resolve));
+    stream.play();
+    const error = await reported;
+    stream.close();
+    expect(error.kind).toBe(StreamError.BackendSpecific);
+    expect(error.description).toContain("instead of 512");
+  });
+
+  test("inputDevices, outputDevices and devicesFiltered split devices", () => {
+    const host = createNullHost({
+      devices: [
+        { name: "Mic", inputChannels: 1, outputChannels: 0, sampleRates: [16000] },
+        { name: "Speakers", inputChannels: 0, outputChannels: 2 },
+        { name: "Interface", inputChannels: 8, outputChannels: 8, sampleRates: [48000, 96000] },
+      ],
+    });
+    const names = (devices: { name(): string }[]) => devices.map((d) => d.name());
+    expect(names(host.inputDevices())).toEqual(["Mic", "Interface"]);
+    expect(names(host.outputDevices())).toEqual(["Speakers", "Interface"]);
+    expect(names(host.devicesFiltered({ direction: DeviceDirection.Input, minChannels: 2 }))).toEqual([
+      "Interface",
+    ]);
+    expect(names(host.devicesFiltered({ sampleRate: 16000 }))).toEqual(["Mic"]);
+    expect(
+      names(host.devicesFiltered((description) => description.direction === DeviceDirection.Duplex)),
+    ).toEqual(["Interface"]);
+  });
+
+  test("findOutputConfig picks the closest supported config", () => {
+    const host = createNullHost({
+      devices: [
+        {
+          inputChannels: 0,
+          outputChannels: 2,
+          sampleRates: [44100, 48000],
+          sampleFormats: [SampleFormat.I16, SampleFormat.F32],
+        },
+      ],
+    });
+    const device = host.defaultOutputDevice()!;
+
+    const nearest = device.findOutputConfig({ sampleRate: 47000, sampleFormat: SampleFormat.I16 });
+    expect(nearest.channels).toBe(2);
+    expect(nearest.sampleRate).toBe(48000);
+    expect(nearest.sampleFormat).toBe(SampleFormat.I16);
+
+    const float = device.findOutputConfig(
+      { sampleRate: 44100, sampleFormat: SampleFormat.I16 },
+      ConfigStrategy.PreferFloat,
+    );
+    expect(float.sampleRate).toBe(44100);
+    expect(float.sampleFormat).toBe(SampleFormat.F32);
+
+    expect(() => device.findOutputConfig({ sampleRate: 22050 }, ConfigStrategy.Exact)).toThrow();
+    expect(device.isOutputConfigSupported(nearest)).toBe(true);
+    expect(device.isOutputConfigSupported({ ...nearest, sampleRate: 22050 })).toBe(false);
+  });
+
+  test("Loopback pair routes output audio to the input", async () => {
+    const host = createNullHost({
+      devices: [],
+      loopbacks: [{ name: "Bus", channels: 1, latencyFrames: 480 }],
+      speed: 10,
+    });
+    const names = host.devices().map((d) => d.name());
+    expect(names).toEqual(["Bus Output", "Bus Input"]);
+
+    const source = new AudioBuffer(1);
+    source.push(new Float32Array(48000).fill(0.5));
+    const sink = new AudioBuffer(1);
+
+    const output = host.defaultOutputDevice()!;
+    const input = host.defaultInputDevice()!;
+    const playback = output.createOutputStream(output.defaultOutputConfig(), source);
+    const capture = input.createInputStream(input.defaultInputConfig(), sink);
+    playback.play();
+    capture.play();
+    const captured = await sink.readExact(4800);
+    playback.close();
+    capture.close();
+
+    expect(captured[0]).toBe(0);
+    expect(captured[4799]).toBe(0.5);
+  });
+
+  test("Offline renderer runs buffer and callback streams", async () => {
+    const renderer = new OfflineRenderer({ channels: 1, blockFrames: 128 });
+    const device = renderer.device();
+    const config = device.defaultOutputConfig();
+
+    const buffer = new AudioBuffer(1);
+    buffer.push(new Float32Array(1000).fill(0.25));
+    const fromBuffer = device.createOutputStream(config, buffer);
+    const fromCallback = device.createOutputStreamWithCallback(config, (frames) =>
+      new Float32Array(frames).fill(0.5),
+    );
+    fromBuffer.play();
+    fromCallback.play();
+
+    const rendered = await renderer.render(2000);
+    fromBuffer.close();
+    fromCallback.close();
+
+    expect(rendered.length).toBe(2000);
+    expect(rendered[0]).toBe(0.75);
+    expect(rendered[1999]).toBe(0.5);
+    expect(renderer.position).toBe(2000);
+  });
+
+  test("renderToFile rejects with code Io when the file cannot be written", async () => {
+    const renderer = new OfflineRenderer({ channels: 1 });
+    const path = join(tmpdir(), "cpal-napi-missing-dir", "out.wav");
+    const error = await renderer.renderToFile(path, 100).catch((e) => e);
+    expect(error.code).toBe("Io");
+    expect(error.message).toContain(path);
+  });
+
+  test("Streams resample buffers to the device rate", async () => {
+    const renderer = new OfflineRenderer({ channels: 1, sampleRate: 48000, blockFrames: 128 });
+    const device = renderer.device();
+    const config = { ...device.defaultOutputConfig(), sampleRate: 44100 };
+
+    const buffer = new AudioBuffer(1, undefined, 24000);
+    expect(buffer.sampleRate).toBe(24000);
+    buffer.push(new Float32Array(500).fill(0.25));
+    const stream = device.createOutputStream(config, buffer);
+    stream.play();
+
+    const rendered = await renderer.render(2000);
+    stream.close();
+
+    // 500 frames at 24 kHz last 1000 frames at 48 kHz.
+    expect(rendered[0]).toBeCloseTo(0.25, 2);
+    expect(rendered[900]).toBeCloseTo(0.25, 2);
+    expect(rendered[1500]).toBe(0);
+  });
+
+  test("resample converts between rates", () => {
+    const input = new Float32Array(4800).map((_, i) => Math.sin((2 * Math.PI * 1000 * i) / 48000));
+    const output = resample(input, 48000, 44100);
+    expect(output.length).toBe(4410);
+    expect(output[441]).toBeCloseTo(Math.sin((2 * Math.PI * 1000 * 441) / 44100), 2);
+
+    const stereo = resample(new Float32Array(200).fill(0.5), 100, 200, 2, ResampleQuality.Fast);
+    expect(stereo.length).toBe(400);
+    expect(stereo[0]).toBe(0.5);
+    expect(() => resample(new Float32Array(3), 48000, 44100, 2)).toThrow();
+  });
+
+  test("recordToFile writes a finalized WAV file", async () => {
+    const host = createNullHost({
+      devices: [{ inputChannels: 1, outputChannels: 0, frequency: 440 }],
+      speed: 10,
+    });
+    const device = host.defaultInputDevice()!;
+    const path = join(tmpdir(), `cpal-napi-record-${Date.now()}.wav`);
+
+    const stream = device.recordToFile(path, device.defaultInputConfig(), {
+      format: WavFormat.Int16,
+    });
+    stream.play();
+    await new Promise((resolve) => setTimeout(resolve, 100));
+    stream.stop();
+
+    const wav = readFileSync(path);
+    rmSync(path);
+    expect(wav.toString("ascii", 0, 4)).toBe("RIFF");
+    const dataBytes = wav.readUInt32LE(76);
+    expect(dataBytes).toBeGreaterThan(0);
+    expect(wav.length).toBe(80 + dataBytes);
+  });
+
+  test("playFile converts a WAV file and reports the end", async () => {
+    const frames = 200;
+    const wav = Buffer.alloc(44 + frames * 2);
+    wav.write("RIFF", 0, "ascii");
+    wav.writeUInt32LE(36 + frames * 2, 4);
+    wav.write("WAVEfmt ", 8, "ascii");
+    wav.writeUInt32LE(16, 16);
+    wav.writeUInt16LE(1, 20);
+    wav.writeUInt16LE(1, 22);
+    wav.writeUInt32LE(24000, 24);
+    wav.writeUInt32LE(48000, 28);
+    wav.writeUInt16LE(2, 32);
+    wav.writeUInt16LE(16, 34);
+    wav.write("data", 36, "ascii");
+    wav.writeUInt32LE(frames * 2, 40);
+    for (let i = 0; i < frames; i++) wav.writeInt16LE(16384, 44 + i * 2);
+    const path = join(tmpdir(), `cpal-napi-play-${Date.now()}.wav`);
+    writeFileSync(path, wav);
+
+    const info = probeAudioFile(path);
+    expect(info.channels).toBe(1);
+    expect(info.sampleRate).toBe(24000);
+    expect(info.duration).toBeCloseTo(frames / 24000, 6);
+
+    const renderer = new OfflineRenderer({ channels: 2, sampleRate: 48000 });
+    const stream = renderer.device().playFile(path);
+    const ended = new Promise<void>((resolve) => stream.onEnded(resolve));
+    stream.play();
+
+    const rendered = await renderer.render(1000);
+    await ended;
+    expect(stream.ended).toBe(true);
+    stream.seek(0);
+    expect(stream.ended).toBe(false);
+    stream.close();
+    rmSync(path);
+
+    expect(rendered[0]).toBeCloseTo(0.5, 3);
+    expect(rendered[1]).toBeCloseTo(0.5, 3);
+    expect(rendered[1999]).toBe(0);
+  });
+
+  test("recordToFile and createEncodedInputStream encode FLAC", async () => {
+    const host = createNullHost({
+      devices: [{ inputChannels: 2, outputChannels: 0, frequency: 440 }],
+      speed: 10,
+    });
+    const device = host.defaultInputDevice()!;
+    const config = device.defaultInputConfig();
+    const path = join(tmpdir(), `cpal-napi-record-${Date.now()}.flac`);
+
+    const chunks: Buffer[] = [];
+    const file = device.recordToFile(path, config);
+    const encoded = device.createEncodedInputStream(config, (chunk) => chunks.push(chunk), {
+      encoding: AudioEncoding.Flac,
+    });
+    file.play();
+    encoded.play();
+    await new Promise((resolve) => setTimeout(resolve, 100));
+    file.stop();
+    encoded.stop();
+    await new Promise((resolve) => setTimeout(resolve, 10));
+
+    const info = probeAudioFile(path);
+    rmSync(path);
+    expect(info.codec).toBe("flac");
+    expect(info.channels).toBe(2);
+    expect(info.frames).toBeGreaterThan(0);
+    const stream = Buffer.concat(chunks);
+    expect(stream.toString("ascii", 0, 4)).toBe("fLaC");
+    expect(stream.length).toBeGreaterThan(42);
+
+    expect(() =>
+      device.createEncodedInputStream(config, () => {}, { encoding: AudioEncoding.Wav }),
+    ).toThrow();
+  });
+});